@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vmc::annotations::parse_annotations;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_annotations(data);
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vmc::op::Op;
+
+fuzz_target!(|data: &[u8]| {
+    let (chunks, _) = data.as_chunks::<2>();
+    let words: Vec<u16> = chunks.iter().cloned().map(u16::from_le_bytes).collect();
+    // Decode the way `decompile` sweeps memory: every suffix, up to the very last word.
+    for addr in 0..=words.len() {
+        if let Ok(op) = Op::try_from(&words[addr..]) {
+            let mut encoded = Vec::new();
+            op.encode(&mut encoded);
+            assert_eq!(encoded, words[addr..addr + 1 + op.arg_count()]);
+        }
+    }
+});
@@ -0,0 +1,30 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vmc::machine::{Buffer, Machine, Mode};
+
+const MAX_STEPS: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&flags, rest)) = data.split_first() else {
+        return;
+    };
+    let (chunks, _) = rest.as_chunks::<2>();
+    let words: Vec<u16> = chunks.iter().cloned().map(u16::from_le_bytes).collect();
+    let mut machine = Machine::with_console(words, Buffer::default());
+    let mode = if flags & 1 == 0 {
+        Mode::Lenient
+    } else {
+        Mode::Strict
+    };
+    machine.set_mode(mode);
+    // Meta-commands reach the host, e.g. `!trace on <path>` writes files.
+    machine.set_meta_prefix(None);
+    // The program bytes double as input for `in`.
+    machine.console_mut().push_input(rest);
+    for _ in 0..MAX_STEPS {
+        if machine.step().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,125 @@
+//! The teleporter's check function, `recursive_func` in the ROM: Ackermann's function with
+//! the eighth register in place of 1 in A(m, 0) = A(m - 1, 1), and everything modulo the word
+//! size.
+//!
+//! ```text
+//! A(0, n) = n + 1
+//! A(m, 0) = A(m - 1, r7)
+//! A(m, n) = A(m - 1, A(m, n - 1))
+//! ```
+//!
+//! Because values wrap, each row A(m, _) is a table of `modulus` entries that can be filled in
+//! from the row below it, which needs neither recursion nor closed forms.
+
+use std::ops::Range;
+
+/// A(m, n) modulo `modulus`, which must not be 0.
+pub fn eval(m: u32, n: u32, r7: u32, modulus: u32) -> u32 {
+    let (n, r7) = (n % modulus, r7 % modulus);
+    if m == 0 {
+        return (n + 1) % modulus;
+    }
+    // row[x] = A(k, x), starting with k = 0, up to k = m - 1.
+    let mut row: Vec<u32> = (0..modulus).map(|x| (x + 1) % modulus).collect();
+    let mut next = vec![0; modulus as usize];
+    for _ in 1..m {
+        next[0] = row[r7 as usize];
+        for x in 1..next.len() {
+            next[x] = row[next[x - 1] as usize];
+        }
+        std::mem::swap(&mut row, &mut next);
+    }
+    // Row m is only needed up to n.
+    (0..n).fold(row[r7 as usize], |a, _| row[a as usize])
+}
+
+/// Every `r7` in `r7s` for which A(m, n) is `target`, in order, tried on all available cores.
+pub fn search(m: u32, n: u32, target: u32, modulus: u32, r7s: Range<u32>) -> Vec<u32> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut found: Vec<u32> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|t| {
+                let r7s = r7s.clone().skip(t).step_by(threads);
+                scope.spawn(move || {
+                    r7s.filter(|&r7| eval(m, n, r7, modulus) == target)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect()
+    });
+    found.sort_unstable();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{
+        error::Error,
+        machine::{Buffer, MOD, Machine, load_rom},
+        op::{Op, Reg, Val},
+        rom,
+    };
+
+    /// Runs the ROM's own `recursive_func` on the VM.
+    fn bytecode(m: u16, n: u16, r7: u16) -> u16 {
+        let mut mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let func = rom::detect(&mem).recursive_func.unwrap();
+        let mut stub = Vec::new();
+        Op::Call(Val::Literal(func)).encode(&mut stub);
+        Op::Halt.encode(&mut stub);
+        mem[..stub.len()].copy_from_slice(&stub);
+        let mut machine = Machine::with_console(mem, Buffer::default());
+        machine.set_register(Reg::REG0, m);
+        machine.set_register(Reg::REG1, n);
+        machine.set_register(Reg::REG7, r7);
+        loop {
+            match machine.step() {
+                Ok(()) => {}
+                Err(Error::Halted) => return machine.registers()[0],
+                Err(e) => panic!("A({m}, {n}) with r7 = {r7}: {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn matches_the_rom() {
+        for m in 0..=3 {
+            for n in 0..=2 {
+                for r7 in 0..=3 {
+                    let want = bytecode(m, n, r7) as u32;
+                    assert_eq!(
+                        eval(m as u32, n as u32, r7 as u32, MOD as u32),
+                        want,
+                        "A({m}, {n}) with r7 = {r7}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn is_ackermann_with_r7_of_1() {
+        // A(2, n) = 2n + 3 and A(3, n) = 2^(n + 3) - 3 while they fit.
+        assert_eq!(eval(2, 4, 1, 1 << 15), 11);
+        assert_eq!(eval(3, 5, 1, 1 << 15), 253);
+        assert_eq!(eval(3, 5, 1, 100), 53);
+    }
+
+    #[test]
+    fn searches_every_r7() {
+        let modulus = 97;
+        let want: Vec<_> = (0..modulus)
+            .filter(|&r7| eval(4, 1, r7, modulus) == 6)
+            .collect();
+        assert_eq!(search(4, 1, 6, modulus, 0..modulus), want);
+        let any = eval(4, 1, 10, modulus);
+        assert!(search(4, 1, any, modulus, 0..modulus).contains(&10));
+    }
+}
@@ -0,0 +1,853 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::op::Reg;
+
+/// A routine in the ROM, from a `[function <name>]` section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub start: u16,
+    /// First address after the function.
+    pub end: u16,
+    /// Free-form signature, e.g. `(r0: m, r1: n) -> r0`.
+    pub signature: Option<String>,
+    pub clobbers: Vec<Reg>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataType {
+    /// Plain 16-bit words.
+    Words,
+    /// A single string: a length word followed by one character per word.
+    String,
+    /// Consecutive length-prefixed strings.
+    Strings,
+}
+
+/// A region of memory that holds data instead of code, from a `[data <name>]` section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataRegion {
+    pub name: String,
+    pub start: u16,
+    pub len: u16,
+    pub ty: DataType,
+}
+
+impl DataRegion {
+    pub fn contains(&self, addr: u16) -> bool {
+        (self.start..self.start.saturating_add(self.len)).contains(&addr)
+    }
+}
+
+/// A named memory cell, from the `[variables]` section. `0x0aac = "current_room: room"` names
+/// the cell and says its values come from `[enum room]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    pub ty: Option<String>,
+}
+
+/// Named constants, from an `[enum <name>]` section.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Enum {
+    pub values: BTreeMap<u16, String>,
+}
+
+/// The layout of a record in memory, from a `[struct <name>]` section: its size in words
+/// and the offset of each named field.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Struct {
+    pub size: u16,
+    pub fields: BTreeMap<String, u16>,
+}
+
+impl Struct {
+    pub fn field(&self, name: &str) -> Option<u16> {
+        self.fields.get(name).copied()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Annotations {
+    pub comments: BTreeMap<u16, Vec<String>>,
+    pub labels: BTreeMap<u16, String>,
+    pub functions: BTreeMap<u16, Function>,
+    pub data: BTreeMap<u16, DataRegion>,
+    pub variables: BTreeMap<u16, Variable>,
+    pub enums: BTreeMap<String, Enum>,
+    pub structs: BTreeMap<String, Struct>,
+}
+
+impl Annotations {
+    /// The label for an address, falling back to the name of a function starting there.
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels
+            .get(&addr)
+            .or_else(|| self.functions.get(&addr).map(|f| &f.name))
+            .map(String::as_str)
+    }
+
+    /// The address a label, function, data region or variable is called `name`.
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        let labels = self.labels.iter().map(|(a, l)| (*a, l.as_str()));
+        let functions = self.functions.values().map(|f| (f.start, f.name.as_str()));
+        let data = self.data.values().map(|d| (d.start, d.name.as_str()));
+        let variables = self.variables.iter().map(|(a, v)| (*a, v.name.as_str()));
+        labels
+            .chain(functions)
+            .chain(data)
+            .chain(variables)
+            .find(|(_, n)| *n == name)
+            .map(|(a, _)| a)
+    }
+
+    pub fn function_containing(&self, addr: u16) -> Option<&Function> {
+        self.functions
+            .range(..=addr)
+            .next_back()
+            .map(|(_, f)| f)
+            .filter(|f| addr < f.end)
+    }
+
+    pub fn data_containing(&self, addr: u16) -> Option<&DataRegion> {
+        self.data
+            .range(..=addr)
+            .next_back()
+            .map(|(_, d)| d)
+            .filter(|d| d.contains(addr))
+    }
+
+    /// Name of `val` in the enum that types the variable at `addr`, if any.
+    pub fn enum_value(&self, addr: u16, val: u16) -> Option<&str> {
+        let ty = self.variables.get(&addr)?.ty.as_ref()?;
+        self.enums.get(ty)?.values.get(&val).map(String::as_str)
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseAnnotationsError {
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseAnnotationsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}:{}: {}", path.display(), self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseAnnotationsError {}
+
+pub fn load_annotations(path: &Path) -> Result<Option<Annotations>, Box<dyn std::error::Error>> {
+    if !std::fs::exists(path)? {
+        return Ok(None);
+    }
+    let annotations = parse_annotations(BufReader::new(File::open(path)?)).map_err(|mut err| {
+        err.path = Some(path.to_owned());
+        err
+    })?;
+    Ok(Some(annotations))
+}
+
+enum AnnotationSection {
+    Unknown,
+    Comments,
+    Labels,
+    Variables,
+    Function(PartialFunction),
+    Data(PartialData),
+    Enum(String),
+    Struct(PartialStruct),
+}
+
+/// A `[function]`, `[data]` or `[struct]` section is only checked for required keys once it ends.
+struct PartialFunction {
+    line: usize,
+    name: String,
+    start: Option<u16>,
+    end: Option<u16>,
+    signature: Option<String>,
+    clobbers: Vec<Reg>,
+}
+
+struct PartialData {
+    line: usize,
+    name: String,
+    start: Option<u16>,
+    len: Option<u16>,
+    ty: Option<DataType>,
+}
+
+struct PartialStruct {
+    line: usize,
+    name: String,
+    size: Option<u16>,
+    /// Each field's offset and the line it's on.
+    fields: BTreeMap<String, (u16, usize)>,
+}
+
+pub fn parse_annotations(reader: impl BufRead) -> Result<Annotations, ParseAnnotationsError> {
+    let mut section = AnnotationSection::Unknown;
+    let mut annotations = Annotations::default();
+    for (i, line) in reader.lines().enumerate() {
+        let line_no = i + 1;
+        let err = |message: String| ParseAnnotationsError {
+            path: None,
+            line: line_no,
+            message,
+        };
+        let line = line.map_err(|e| err(e.to_string()))?;
+        let l = line.trim();
+        if l.is_empty() || l.starts_with(';') || l.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = l.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| err(format!("unterminated section header {l:?}")))?;
+            let next = match header.split_once(' ') {
+                None => match header {
+                    "comments" => AnnotationSection::Comments,
+                    "labels" => AnnotationSection::Labels,
+                    "variables" => AnnotationSection::Variables,
+                    _ => return Err(err(format!("unknown section [{header}]"))),
+                },
+                Some(("function", name)) => AnnotationSection::Function(PartialFunction {
+                    line: line_no,
+                    name: name.trim().to_owned(),
+                    start: None,
+                    end: None,
+                    signature: None,
+                    clobbers: Vec::new(),
+                }),
+                Some(("data", name)) => AnnotationSection::Data(PartialData {
+                    line: line_no,
+                    name: name.trim().to_owned(),
+                    start: None,
+                    len: None,
+                    ty: None,
+                }),
+                Some(("struct", name)) => AnnotationSection::Struct(PartialStruct {
+                    line: line_no,
+                    name: name.trim().to_owned(),
+                    size: None,
+                    fields: BTreeMap::new(),
+                }),
+                Some(("enum", name)) => {
+                    let name = name.trim().to_owned();
+                    if annotations.enums.contains_key(&name) {
+                        return Err(err(format!("duplicate enum {name}")));
+                    }
+                    annotations.enums.insert(name.clone(), Enum::default());
+                    AnnotationSection::Enum(name)
+                }
+                Some(_) => return Err(err(format!("unknown section [{header}]"))),
+            };
+            finish_section(std::mem::replace(&mut section, next), &mut annotations)?;
+            continue;
+        }
+
+        let (left, right) = l
+            .split_once('=')
+            .ok_or_else(|| err(format!("expected `key = value`, found {l:?}")))?;
+        let (left, right) = (left.trim(), right.trim());
+        match &mut section {
+            AnnotationSection::Unknown => {
+                return Err(err("entry before the first section".to_owned()));
+            }
+            AnnotationSection::Comments => {
+                let addr = parse_addr(left).map_err(err)?;
+                let text = parse_string(right).map_err(err)?;
+                annotations.comments.entry(addr).or_default().push(text);
+            }
+            AnnotationSection::Labels => {
+                let addr = parse_addr(left).map_err(err)?;
+                let text = parse_string(right).map_err(err)?;
+                if let Some(prev) = annotations.labels.insert(addr, text) {
+                    return Err(err(format!("0x{addr:04x} is already labelled {prev}")));
+                }
+            }
+            AnnotationSection::Variables => {
+                let addr = parse_addr(left).map_err(err)?;
+                let text = parse_string(right).map_err(err)?;
+                let var = match text.split_once(':') {
+                    Some((name, ty)) => Variable {
+                        name: name.trim().to_owned(),
+                        ty: Some(ty.trim().to_owned()),
+                    },
+                    None => Variable {
+                        name: text,
+                        ty: None,
+                    },
+                };
+                annotations.variables.insert(addr, var);
+            }
+            AnnotationSection::Function(f) => match left {
+                "start" => f.start = Some(parse_addr(right).map_err(err)?),
+                "end" => f.end = Some(parse_addr(right).map_err(err)?),
+                "signature" => f.signature = Some(parse_string(right).map_err(err)?),
+                "clobbers" => f.clobbers = parse_regs(right).map_err(err)?,
+                _ => return Err(err(format!("unknown function key {left:?}"))),
+            },
+            AnnotationSection::Data(d) => match left {
+                "start" => d.start = Some(parse_addr(right).map_err(err)?),
+                "len" => d.len = Some(parse_number(right).map_err(err)?),
+                "type" => {
+                    d.ty = Some(match right {
+                        "words" => DataType::Words,
+                        "string" => DataType::String,
+                        "strings" => DataType::Strings,
+                        _ => return Err(err(format!("unknown data type {right:?}"))),
+                    })
+                }
+                _ => return Err(err(format!("unknown data key {left:?}"))),
+            },
+            AnnotationSection::Struct(s) => {
+                let val = parse_number(right).map_err(err)?;
+                if left == "size" {
+                    s.size = Some(val);
+                } else if !is_label(left) {
+                    return Err(err(format!("invalid field name {left:?}")));
+                } else if s.fields.insert(left.to_owned(), (val, line_no)).is_some() {
+                    return Err(err(format!("duplicate field {left} in struct {}", s.name)));
+                }
+            }
+            AnnotationSection::Enum(name) => {
+                let val = parse_number(right).map_err(err)?;
+                let values = &mut annotations.enums.get_mut(name).unwrap().values;
+                if let Some(prev) = values.insert(val, left.to_owned()) {
+                    return Err(err(format!("{val} is already {prev} in enum {name}")));
+                }
+            }
+        }
+    }
+    finish_section(section, &mut annotations)?;
+    Ok(annotations)
+}
+
+fn finish_section(
+    section: AnnotationSection,
+    annotations: &mut Annotations,
+) -> Result<(), ParseAnnotationsError> {
+    let missing = |line: usize, what: &str, name: &str, key: &str| ParseAnnotationsError {
+        path: None,
+        line,
+        message: format!("{what} {name} is missing `{key}`"),
+    };
+    match section {
+        AnnotationSection::Function(f) => {
+            let start = f
+                .start
+                .ok_or_else(|| missing(f.line, "function", &f.name, "start"))?;
+            let end = f
+                .end
+                .ok_or_else(|| missing(f.line, "function", &f.name, "end"))?;
+            if end <= start {
+                return Err(ParseAnnotationsError {
+                    path: None,
+                    line: f.line,
+                    message: format!("function {} ends before it starts", f.name),
+                });
+            }
+            annotations.functions.insert(
+                start,
+                Function {
+                    name: f.name,
+                    start,
+                    end,
+                    signature: f.signature,
+                    clobbers: f.clobbers,
+                },
+            );
+        }
+        AnnotationSection::Data(d) => {
+            let start = d
+                .start
+                .ok_or_else(|| missing(d.line, "data", &d.name, "start"))?;
+            let len = d
+                .len
+                .ok_or_else(|| missing(d.line, "data", &d.name, "len"))?;
+            if len == 0 {
+                return Err(ParseAnnotationsError {
+                    path: None,
+                    line: d.line,
+                    message: format!("data {} is empty", d.name),
+                });
+            }
+            annotations.data.insert(
+                start,
+                DataRegion {
+                    name: d.name,
+                    start,
+                    len,
+                    ty: d.ty.unwrap_or(DataType::Words),
+                },
+            );
+        }
+        AnnotationSection::Struct(s) => {
+            if annotations.structs.contains_key(&s.name) {
+                return Err(ParseAnnotationsError {
+                    path: None,
+                    line: s.line,
+                    message: format!("duplicate struct {}", s.name),
+                });
+            }
+            let size = s
+                .size
+                .ok_or_else(|| missing(s.line, "struct", &s.name, "size"))?;
+            let mut fields = BTreeMap::new();
+            for (name, (offset, line)) in s.fields {
+                if offset >= size {
+                    return Err(ParseAnnotationsError {
+                        path: None,
+                        line,
+                        message: format!("field {name} is past the end of struct {}", s.name),
+                    });
+                }
+                fields.insert(name, offset);
+            }
+            annotations.structs.insert(s.name, Struct { size, fields });
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Addresses are always hex, with or without `0x`.
+fn parse_addr(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("invalid address {s:?}: {e}"))
+}
+
+/// Other numbers are decimal unless prefixed with `0x`.
+fn parse_number(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("invalid number {s:?}: {e}"))
+}
+
+fn parse_string(s: &str) -> Result<String, String> {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .map(str::to_owned)
+        .ok_or_else(|| format!("expected a quoted string, found {s:?}"))
+}
+
+fn parse_regs(s: &str) -> Result<Vec<Reg>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|r| r.parse().map_err(|_| format!("invalid register {r:?}")))
+        .collect()
+}
+
+/// An annotations file kept line by line, so that adding entries leaves every existing line,
+/// comment and ordering as it was.
+pub struct AnnotationsFile {
+    path: PathBuf,
+    lines: Vec<String>,
+    annotations: Annotations,
+}
+
+impl AnnotationsFile {
+    /// Opens `path`, or starts an empty file there if it doesn't exist yet.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = if std::fs::exists(path)? {
+            std::fs::read_to_string(path)?
+        } else {
+            String::new()
+        };
+        let annotations = parse_annotations(text.as_bytes()).map_err(|mut err| {
+            err.path = Some(path.to_owned());
+            err
+        })?;
+        Ok(Self {
+            path: path.to_owned(),
+            lines: text.lines().map(str::to_owned).collect(),
+            annotations,
+        })
+    }
+
+    pub fn annotations(&self) -> &Annotations {
+        &self.annotations
+    }
+
+    /// Labels `addr`, renaming its existing label if it has one.
+    pub fn set_label(&mut self, addr: u16, name: &str) -> Result<(), String> {
+        if !is_label(name) {
+            return Err(format!("invalid label {name:?}"));
+        }
+        if let Some((&other, _)) = self
+            .annotations
+            .labels
+            .iter()
+            .find(|(a, l)| l.as_str() == name && **a != addr)
+        {
+            return Err(format!("{name} already labels 0x{other:04x}"));
+        }
+        let entry = format!("0x{addr:04x} = \"{name}\"");
+        match self.find_entry("labels", addr) {
+            Some(i) => self.lines[i] = entry,
+            None => self.insert_entry("labels", addr, entry),
+        }
+        self.annotations.labels.insert(addr, name.to_owned());
+        Ok(())
+    }
+
+    /// Adds a comment to `addr`, after any comments it already has.
+    pub fn add_comment(&mut self, addr: u16, text: &str) -> Result<(), String> {
+        if text.contains('"') || text.contains('\n') {
+            return Err(format!(
+                "comments can't contain quotes or newlines: {text:?}"
+            ));
+        }
+        self.insert_entry("comments", addr, format!("0x{addr:04x} = \"{text}\""));
+        self.annotations
+            .comments
+            .entry(addr)
+            .or_default()
+            .push(text.to_owned());
+        Ok(())
+    }
+
+    /// Appends a `[function]` section for `f`. Functions that are already annotated are left
+    /// alone.
+    pub fn add_function(&mut self, f: &Function) -> Result<(), String> {
+        if !is_label(&f.name) {
+            return Err(format!("invalid function name {:?}", f.name));
+        }
+        if let Some(other) = self.annotations.functions.get(&f.start) {
+            return Err(format!(
+                "0x{:04x} is already annotated as function {}",
+                f.start, other.name
+            ));
+        }
+        if f.signature.as_ref().is_some_and(|s| s.contains('"')) {
+            return Err(format!(
+                "signatures can't contain quotes: {:?}",
+                f.signature
+            ));
+        }
+        if self.lines.last().is_some_and(|l| !l.trim().is_empty()) {
+            self.lines.push(String::new());
+        }
+        self.lines.push(format!("[function {}]", f.name));
+        self.lines.push(format!("start = 0x{:04x}", f.start));
+        self.lines.push(format!("end = 0x{:04x}", f.end));
+        if let Some(sig) = &f.signature {
+            self.lines.push(format!("signature = \"{sig}\""));
+        }
+        if !f.clobbers.is_empty() {
+            let regs: Vec<_> = f.clobbers.iter().map(|r| r.to_string()).collect();
+            self.lines.push(format!("clobbers = {}", regs.join(", ")));
+        }
+        self.annotations.functions.insert(f.start, f.clone());
+        Ok(())
+    }
+
+    /// Labels the targets of literal calls `sub_XXXX` and of literal jumps `loc_XXXX`, skipping
+    /// addresses that already have a label. Returns how many labels were added.
+    pub fn auto_label(&mut self, mem: &[u16]) -> usize {
+        let ops = crate::cfg::sweep(mem, &self.annotations);
+        let (calls, jumps) = crate::cfg::targets(&ops);
+        let mut added = 0;
+        for (prefix, targets) in [("sub", calls.clone()), ("loc", &jumps - &calls)] {
+            for addr in targets {
+                if self.annotations.label(addr).is_some() {
+                    continue;
+                }
+                let name = format!("{prefix}_{addr:04x}");
+                if self.set_label(addr, &name).is_ok() {
+                    added += 1;
+                }
+            }
+        }
+        added
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        std::fs::write(&self.path, text)
+    }
+
+    /// Lines that belong to `[section]`, not counting its header.
+    fn section(&self, section: &str) -> Option<std::ops::Range<usize>> {
+        let header = format!("[{section}]");
+        let start = self.lines.iter().position(|l| l.trim() == header)? + 1;
+        let len = self.lines[start..]
+            .iter()
+            .position(|l| l.trim_start().starts_with('['))
+            .unwrap_or(self.lines.len() - start);
+        Some(start..start + len)
+    }
+
+    fn entry_addr(line: &str) -> Option<u16> {
+        let (left, _) = line.split_once('=')?;
+        parse_addr(left.trim()).ok()
+    }
+
+    fn find_entry(&self, section: &str, addr: u16) -> Option<usize> {
+        self.section(section)?
+            .find(|&i| Self::entry_addr(&self.lines[i]) == Some(addr))
+    }
+
+    /// Inserts after the last entry of the section with an address at or before `addr`, so
+    /// sorted sections stay sorted. Missing sections are added at the end of the file.
+    fn insert_entry(&mut self, section: &str, addr: u16, entry: String) {
+        let Some(range) = self.section(section) else {
+            if self.lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                self.lines.push(String::new());
+            }
+            self.lines.push(format!("[{section}]"));
+            self.lines.push(entry);
+            return;
+        };
+        let mut at = range.start;
+        for i in range {
+            match Self::entry_addr(&self.lines[i]) {
+                Some(a) if a <= addr => at = i + 1,
+                Some(_) => break,
+                None => {}
+            }
+        }
+        self.lines.insert(at, entry);
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+; comments and blank lines are skipped
+[comments]
+0x1587 = "recursive_func(4, 1)"
+0x1587 = "second comment"
+
+[labels]
+0x17a9 = "r0_not_zero"
+
+[function recursive_func]
+start = 0x17a1
+end = 0x17ca
+signature = "(r0: m, r1: n) -> r0"
+clobbers = r1
+
+[data greeting]
+start = 0x0010
+len = 6
+type = string
+
+[variables]
+0x0aac = "current_room: room"
+0x0aad = "counter"
+
+[enum room]
+foothills = 0x090d
+dark_passage = 2345
+
+[struct item]
+size = 4
+name = 0
+location = 2
+"#;
+
+    fn parse(s: &str) -> Result<Annotations, ParseAnnotationsError> {
+        parse_annotations(s.as_bytes())
+    }
+
+    #[test]
+    fn parses_every_section() {
+        let a = parse(SAMPLE).unwrap();
+        assert_eq!(
+            a.comments[&0x1587],
+            ["recursive_func(4, 1)", "second comment"]
+        );
+        assert_eq!(a.label(0x17a9), Some("r0_not_zero"));
+        assert_eq!(a.label(0x17a1), Some("recursive_func"));
+        let f = a.function_containing(0x17c9).unwrap();
+        assert_eq!(f.name, "recursive_func");
+        assert_eq!(f.signature.as_deref(), Some("(r0: m, r1: n) -> r0"));
+        assert_eq!(f.clobbers, [Reg::REG1]);
+        assert!(a.function_containing(0x17ca).is_none());
+        let d = a.data_containing(0x0015).unwrap();
+        assert_eq!(
+            (d.name.as_str(), d.len, d.ty),
+            ("greeting", 6, DataType::String)
+        );
+        assert!(a.data_containing(0x0016).is_none());
+        assert_eq!(a.variables[&0x0aad].ty, None);
+        assert_eq!(a.enum_value(0x0aac, 0x090d), Some("foothills"));
+        assert_eq!(a.enum_value(0x0aac, 2345), Some("dark_passage"));
+        assert_eq!(a.enum_value(0x0aad, 2345), None);
+        let item = &a.structs["item"];
+        assert_eq!(item.size, 4);
+        assert_eq!(item.field("location"), Some(2));
+        assert_eq!(item.field("size"), None);
+    }
+
+    #[test]
+    fn parses_repo_annotations() {
+        let file = std::fs::read_to_string("annotations.ini").unwrap();
+        let a = parse(&file).unwrap();
+        assert_eq!(a.label(0x17a1), Some("recursive_func"));
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let cases = [
+            ("0x1 = \"x\"", 1, "entry before the first section"),
+            (
+                "[comments]\n\n0x1587 recursive",
+                3,
+                "expected `key = value`",
+            ),
+            ("[labels]\nzz = \"x\"", 2, "invalid address"),
+            ("[labels]\n0x1 = x", 2, "expected a quoted string"),
+            (
+                "[labels]\n0x1 = \"a\"\n0x1 = \"b\"",
+                3,
+                "already labelled a",
+            ),
+            ("[nope]", 1, "unknown section [nope]"),
+            ("[comments", 1, "unterminated section header"),
+            (
+                "[function f]\nstart = 0x10\nret = r0",
+                3,
+                "unknown function key",
+            ),
+            (
+                "[function f]\nstart = 0x10\n[labels]",
+                1,
+                "function f is missing `end`",
+            ),
+            (
+                "[function f]\nstart = 0x10\nend = 0x10",
+                1,
+                "ends before it starts",
+            ),
+            ("[data z]\nstart = 0x0000\nlen = 0", 1, "data z is empty"),
+            (
+                "[function f]\nclobbers = r1, r8",
+                2,
+                "invalid register \"r8\"",
+            ),
+            (
+                "[data d]\nstart = 0x10\ntype = floats",
+                3,
+                "unknown data type",
+            ),
+            ("[data d]\nlen = 4", 1, "data d is missing `start`"),
+            ("[enum e]\na = 1\nb = 1", 3, "1 is already a in enum e"),
+            ("[enum e]\n[enum e]", 2, "duplicate enum e"),
+            ("[struct s]\nname = 0", 1, "struct s is missing `size`"),
+            (
+                "[struct s]\nsize = 2\nname = 0\nnext = 2",
+                4,
+                "field next is past the end of struct s",
+            ),
+            (
+                "[struct s]\nsize = 2\nname = 0\nname = 1",
+                4,
+                "duplicate field name",
+            ),
+            ("[struct s]\nsize = 2\n[struct s]", 3, "duplicate struct s"),
+        ];
+        for (input, line, message) in cases {
+            let err = parse(input).unwrap_err();
+            assert_eq!(err.line, line, "{input:?}: {err}");
+            assert!(err.message.contains(message), "{input:?}: {err}");
+        }
+    }
+
+    fn temp_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("vmc-{}-{name}.ini", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn edits_keep_existing_lines() {
+        let path = temp_file(
+            "edits",
+            "; notes\n[comments]\n0x0010 = \"a\"\n\n[labels]\n0x0001 = \"one\"\n0x0009 = \"nine\"\n",
+        );
+        let mut file = AnnotationsFile::open(&path).unwrap();
+        file.set_label(0x0005, "five").unwrap();
+        file.set_label(0x0009, "renamed").unwrap();
+        file.add_comment(0x0010, "b").unwrap();
+        file.add_comment(0x0002, "c").unwrap();
+        assert!(file.set_label(0x0003, "one").is_err());
+        assert!(file.set_label(0x0003, "not a label").is_err());
+        file.save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            text,
+            "; notes\n[comments]\n0x0002 = \"c\"\n0x0010 = \"a\"\n0x0010 = \"b\"\n\n\
+             [labels]\n0x0001 = \"one\"\n0x0005 = \"five\"\n0x0009 = \"renamed\"\n"
+        );
+        let a = parse(&text).unwrap();
+        assert_eq!(a.comments[&0x10], ["a", "b"]);
+        assert_eq!(a.label(9), Some("renamed"));
+    }
+
+    #[test]
+    fn auto_label_adds_missing_sections() {
+        let path = temp_file("auto", "[labels]\n0x0008 = \"known\"\n");
+        let mut file = AnnotationsFile::open(&path).unwrap();
+        // 0: call 5, 2: jmp 8, 4: halt, 5: jt r0, 4, 8: ret... except 8 is already labelled.
+        let mem = [17, 5, 6, 8, 0, 7, 32768, 4, 18];
+        assert_eq!(file.auto_label(&mem), 2);
+        file.add_comment(0, "entry").unwrap();
+        file.save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            text,
+            "[labels]\n0x0004 = \"loc_0004\"\n0x0005 = \"sub_0005\"\n0x0008 = \"known\"\n\n\
+             [comments]\n0x0000 = \"entry\"\n"
+        );
+    }
+
+    #[test]
+    fn add_function_round_trips() {
+        let path = temp_file("function", "[function known]\nstart = 0x10\nend = 0x20\n");
+        let mut file = AnnotationsFile::open(&path).unwrap();
+        let f = Function {
+            name: "sub_0030".to_owned(),
+            start: 0x30,
+            end: 0x3a,
+            signature: Some("(r0) -> r1".to_owned()),
+            clobbers: vec![Reg::REG2],
+        };
+        file.add_function(&f).unwrap();
+        let mut known = f.clone();
+        known.start = 0x10;
+        assert!(file.add_function(&known).is_err());
+        file.save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(parse(&text).unwrap().functions[&0x30], f);
+    }
+}
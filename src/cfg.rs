@@ -0,0 +1,49 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    annotations::Annotations,
+    op::{Op, Val},
+};
+
+/// Decodes memory front to back the way `decompile` prints it: data regions are skipped and
+/// every other word that starts a valid op is taken as code.
+pub fn sweep(mem: &[u16], annotations: &Annotations) -> BTreeMap<u16, Op> {
+    let mut ops = BTreeMap::new();
+    let mut addr = 0;
+    while addr < mem.len() {
+        if let Some(data) = annotations.data.get(&(addr as u16)) {
+            addr += data.len.max(1) as usize;
+            continue;
+        }
+        match Op::try_from(&mem[addr..]) {
+            Ok(op) => {
+                ops.insert(addr as u16, op);
+                addr += 1 + op.arg_count();
+            }
+            Err(_) => addr += 1,
+        }
+    }
+    ops
+}
+
+/// Literal `call` targets and literal `jmp`/`jt`/`jf` targets of `ops` that land on an op.
+pub fn targets(ops: &BTreeMap<u16, Op>) -> (BTreeSet<u16>, BTreeSet<u16>) {
+    let mut calls = BTreeSet::new();
+    let mut jumps = BTreeSet::new();
+    for op in ops.values() {
+        match *op {
+            Op::Call(Val::Literal(to)) => {
+                calls.insert(to);
+            }
+            Op::Jmp(Val::Literal(to))
+            | Op::Jt(_, Val::Literal(to))
+            | Op::Jf(_, Val::Literal(to)) => {
+                jumps.insert(to);
+            }
+            _ => {}
+        }
+    }
+    calls.retain(|a| ops.contains_key(a));
+    jumps.retain(|a| ops.contains_key(a));
+    (calls, jumps)
+}
@@ -0,0 +1,187 @@
+//! Cheats for getting to a late puzzle without playing up to it: each one writes the words
+//! of game state that [`crate::state`] decodes, through [`Machine::write_mem`].
+//!
+//! ```text
+//! teleport <room>            make <room>, a name or an address, the current room
+//! give <item>                put <item> in the inventory
+//! set-flag <name> [<value>]  set the [variables] entry <name> to <value> [default: 1]
+//! ```
+//!
+//! The game only notices a teleport on the next command, e.g. `look`.
+
+use crate::{
+    machine::{Console, Machine},
+    state::Layout,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cheat {
+    Teleport(String),
+    Give(String),
+    SetFlag(String, u16),
+}
+
+impl std::str::FromStr for Cheat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = s.trim().split_once(' ').unwrap_or((s.trim(), ""));
+        let arg = arg.trim();
+        match name {
+            "teleport" if arg.is_empty() => Err("usage: teleport <room>".to_owned()),
+            "give" if arg.is_empty() => Err("usage: give <item>".to_owned()),
+            "teleport" => Ok(Cheat::Teleport(arg.to_owned())),
+            "give" => Ok(Cheat::Give(arg.to_owned())),
+            "set-flag" => match arg.split_whitespace().collect::<Vec<_>>()[..] {
+                [flag] => Ok(Cheat::SetFlag(flag.to_owned(), 1)),
+                [flag, value] => {
+                    let value = value
+                        .parse()
+                        .map_err(|e| format!("invalid value {value:?}: {e}"))?;
+                    Ok(Cheat::SetFlag(flag.to_owned(), value))
+                }
+                _ => Err("usage: set-flag <name> [<value>]".to_owned()),
+            },
+            _ => Err(format!("unknown cheat {name:?}")),
+        }
+    }
+}
+
+/// Applies `cheat` using the layout in the machine's annotations, and says what it did.
+pub fn apply<C: Console>(machine: &mut Machine<C>, cheat: &Cheat) -> Result<String, String> {
+    let layout = Layout::from_annotations(machine.annotations())?;
+    let mem = machine.mem();
+    let (addr, val, done) = match cheat {
+        Cheat::Teleport(room) => {
+            let rooms = layout.rooms(mem)?;
+            let found: Vec<_> = match room.strip_prefix("0x") {
+                Some(hex) => {
+                    let addr = u16::from_str_radix(hex, 16)
+                        .map_err(|e| format!("invalid address {room:?}: {e}"))?;
+                    rooms.iter().filter(|r| r.addr == addr).collect()
+                }
+                None => rooms.iter().filter(|r| r.name == *room).collect(),
+            };
+            let to = match found[..] {
+                [to] => to,
+                [] => return Err(format!("no room {room:?}")),
+                _ => {
+                    let addrs: Vec<_> = found.iter().map(|r| format!("0x{:04x}", r.addr)).collect();
+                    return Err(format!(
+                        "{room:?} is ambiguous, use one of the addresses {}",
+                        addrs.join(", ")
+                    ));
+                }
+            };
+            let done = format!("teleported to {} (0x{:04x})", to.name, to.addr);
+            (layout.current_room, to.addr, done)
+        }
+        Cheat::Give(item) => {
+            let item = layout.find_item(mem, item)?;
+            let done = format!("the {} is in the inventory", item.name);
+            (item.addr + layout.item.location, layout.inventory, done)
+        }
+        Cheat::SetFlag(name, val) => {
+            let addr = machine
+                .annotations()
+                .variables
+                .iter()
+                .find(|(_, v)| v.name == *name)
+                .map(|(a, _)| *a)
+                .ok_or_else(|| format!("no variable {name:?}"))?;
+            (addr, *val, format!("{name} = {val}"))
+        }
+    };
+    machine.write_mem(addr, val).map_err(|e| e.to_string())?;
+    Ok(done)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{
+        annotations::load_annotations,
+        coins,
+        error::Error,
+        machine::{Buffer, load_rom},
+        orb,
+    };
+
+    /// A machine at the start of the game, with the repo's annotations.
+    fn start() -> Machine<Buffer> {
+        let mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let mut machine = Machine::with_console(mem, Buffer::default());
+        let annotations = load_annotations(Path::new("annotations.ini"))
+            .unwrap()
+            .unwrap();
+        machine.set_annotations(annotations);
+        assert!(matches!(machine.run(), Err(Error::InputExhausted(_))));
+        machine
+    }
+
+    fn cheat(machine: &mut Machine<Buffer>, cheat: &str) -> Result<String, String> {
+        apply(machine, &cheat.parse()?)
+    }
+
+    #[test]
+    fn parses_cheats() {
+        assert_eq!(
+            "teleport Vault Antechamber".parse(),
+            Ok(Cheat::Teleport("Vault Antechamber".to_owned()))
+        );
+        assert_eq!("set-flag f".parse(), Ok(Cheat::SetFlag("f".to_owned(), 1)));
+        assert_eq!(
+            "set-flag f 5".parse(),
+            Ok(Cheat::SetFlag("f".to_owned(), 5))
+        );
+        assert_eq!(
+            "give".parse::<Cheat>(),
+            Err("usage: give <item>".to_owned())
+        );
+        assert!("set-flag f x".parse::<Cheat>().is_err());
+        assert!("fly".parse::<Cheat>().is_err());
+    }
+
+    #[test]
+    fn skips_to_the_coins() {
+        let mut machine = start();
+        assert_eq!(
+            cheat(&mut machine, "teleport Ruins").unwrap_err(),
+            "\"Ruins\" is ambiguous, use one of the addresses 0x09a5, 0x09aa, 0x09af, 0x09b5, 0x09ba, 0x09bf, 0x09c4, 0x09c9"
+        );
+        cheat(&mut machine, "teleport 0x09af").unwrap();
+        for coin in ["red", "corroded", "shiny", "concave", "blue"] {
+            cheat(&mut machine, &format!("give {coin} coin")).unwrap();
+        }
+        let (equation, coins) = coins::read(&mut machine).unwrap();
+        assert_eq!(coins.len(), 5);
+        assert!(coins::solve(&equation, &coins).is_some());
+
+        assert!(
+            machine
+                .command("north")
+                .unwrap()
+                .contains("That door is locked.")
+        );
+        assert_eq!(
+            cheat(&mut machine, "set-flag coins_placed 5"),
+            Ok("coins_placed = 5".to_owned())
+        );
+        assert!(machine.command("north").unwrap().contains("- teleporter"));
+    }
+
+    #[test]
+    fn skips_to_the_vault() {
+        let mut machine = start();
+        assert_eq!(
+            cheat(&mut machine, "teleport Vault Antechamber"),
+            Ok("teleported to Vault Antechamber (0x0a55)".to_owned())
+        );
+        let grid = orb::read(&mut machine).unwrap();
+        assert_eq!(grid.target, 30);
+        assert!(cheat(&mut machine, "give unicorn").is_err());
+        assert!(cheat(&mut machine, "set-flag nothing").is_err());
+    }
+}
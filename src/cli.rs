@@ -0,0 +1,677 @@
+use std::path::PathBuf;
+
+use vmc::{disasm::Options, idiom::Idioms, machine::Mode};
+
+pub const USAGE: &str = "\
+usage: vmc [command] [options]
+
+commands:
+  run          run the ROM interactively (the default)
+  decompile    disassemble the ROM with annotations
+  annotate     add labels and comments to the annotations file
+  reg8         search for the eighth register value for the teleporter
+  info         identify the ROM and show what is known about it
+  scan         find routines by code pattern
+  functions    list functions with their registers and stack use
+  xref         list references to and from an address
+  explore      map every room reachable from the start
+  route        print the moves from one room to another as a script
+  solve        solve a puzzle in the game and print the solution as a script
+  codes        find the codes in a transcript and check them
+  state        show the room, inventory and item locations read from memory
+  help         show help for a command
+
+options for every command:
+  --rom <file>            ROM to load [default: challenge.bin]
+  --annotations <file>    annotations file [default: annotations.ini]
+  -h, --help              show help";
+
+const RUN_USAGE: &str = "\
+usage: vmc run [options]
+
+options:
+  --script <file>         feed the file to the VM as input before reading stdin, see below
+  --trace                 trace every op to run.trace
+  --trace-out <file>      trace every op to <file>
+  --max-steps <n>         stop with an error after executing <n> ops
+  --strict                stop on any arch-spec violation
+  --lenient               apply fallbacks for arch-spec violations [default]
+  --hack-teleporter       skip the teleporter's check of r7, and let !setreg r7 and
+                          @setreg r7 set it to the value known for the ROM
+  --meta-prefix <prefix>  what meta-command lines start with, or \"\" for none [default: !]
+
+Lines of input that start with ! are meta-commands, which the game doesn't see:
+  !state                  show the room, inventory and item locations, as `vmc state` does
+  !teleport <room>        move to <room>, a name or an address, seen on the next command
+  !give <item>            put <item> in the inventory
+  !set-flag <name> [<n>]  set the annotated variable <name> to <n> [default: 1]
+  !save [<name>]          take a snapshot
+  !load [<name>]          go back to a snapshot
+  !regs                   show the registers
+  !setreg <reg> <n>       set a register, e.g. !setreg r7 25734
+  !setreg r7              set r7 to the ROM's value, with --hack-teleporter
+  !break [<addr|name>]    set or clear a breakpoint, or list them; at a breakpoint, enter
+                          meta-commands without the !, then `continue`
+  !label <addr> <name>    label <addr> in the annotations file
+  !comment <addr> <text>  add a comment to <addr> in the annotations file
+  !trace on [<file>]      trace every op to <file> [default: run.trace]
+  !trace off              stop tracing
+  !resume                 go on with the script after its @pause
+  !quit                   stop the game
+
+Script lines are typed as they are, except for // comments and these directives:
+  @expect \"<text>\"        stop unless the game printed <text> since the last input
+  @setreg <reg> <n>       set a register, e.g. @setreg r7 25734
+  @setreg r7              set r7 to the ROM's value, with --hack-teleporter
+  @patch <addr> <n>...    write to memory from <addr> on, in hex
+  @snapshot <name>        take a snapshot, for !load <name>
+  @include <file>         play <file>, relative to the script, here
+  @pause                  read from stdin until !resume
+  @quiet                  hide the game's output and the script's input
+  @echo                   show them again, as by default, after the script or a @pause";
+
+const DECOMPILE_USAGE: &str = "\
+usage: vmc decompile [options]
+
+options:
+  --high-level            print functions as pseudo-C instead of one op per line
+  --idioms <list>         pseudo-ops to show next to ops: all, none, or a comma-separated
+                          list of sub, neg, save and icall [default: all]
+  --values                show register values known statically, and where indirect calls go";
+
+const ANNOTATE_USAGE: &str = "\
+usage: vmc annotate label <addr> <name>
+       vmc annotate comment <addr> <text>...
+       vmc annotate auto
+
+auto labels literal call targets sub_XXXX and jump targets loc_XXXX.
+<addr> is hex, with or without 0x.";
+
+const REG8_USAGE: &str = "\
+usage: vmc reg8
+
+Tries every r7 in the teleporter check, A(4, 1) = 6, on all available cores.";
+
+const INFO_USAGE: &str = "\
+usage: vmc info
+
+Known ROMs are recognized by fingerprint. For others, the teleporter check is searched for.";
+
+const FUNCTIONS_USAGE: &str = "\
+usage: vmc functions [--export]
+
+Lists every function with the registers it reads as arguments, returns and clobbers, its
+maximum stack depth, and any paths that leave the stack unbalanced.
+
+options:
+  --export                add [function] sections for functions that aren't annotated yet";
+
+const SCAN_USAGE: &str = "\
+usage: vmc scan [--label] [<pattern>]
+
+Without a pattern, looks for every known routine.
+
+A pattern is ops or raw words separated by ';', e.g. \"set r0 4; set r1 1; >call ?\".
+? matches any word, and the match is reported at the item marked with >, if any.
+
+options:
+  --label                 label the known routines that are found in the annotations file";
+
+const XREF_USAGE: &str = "\
+usage: vmc xref [--boot] <addr|name>
+
+Lists the calls, jumps, memory reads and writes and address constants that refer to <addr>,
+and what the op at <addr> refers to. <name> is a label, function, data region or variable.
+
+options:
+  --boot                  run the ROM until it first waits for input, so that strings it
+                          decrypts at startup are found";
+
+const EXPLORE_USAGE: &str = "\
+usage: vmc explore [options]
+
+Tries every exit of every room, breadth-first, and prints the rooms with their items and
+where their exits lead.
+
+options:
+  --script <file>         play the file first, and explore from where it ends
+  --hack-teleporter       skip the teleporter's check of r7, for scripts that set it
+  --format <format>       json, or dot for Graphviz [default: json]";
+
+const ROUTE_USAGE: &str = "\
+usage: vmc route [--script <file> [--hack-teleporter]] <from> <to>
+
+Explores the map, then prints the fewest `go` commands from room <from> to room <to> in
+--script format. A room is its name or its id from `vmc explore`. If several rooms have
+the <to> name, the route goes to the nearest one.
+
+options:
+  --script <file>         play the file first, and explore from where it ends
+  --hack-teleporter       skip the teleporter's check of r7, for scripts that set it";
+
+const SOLVE_USAGE: &str = "\
+usage: vmc solve coins [--script <file> [--hack-teleporter]]
+       vmc solve orb [--script <file> [--hack-teleporter] | --grid <file>]
+
+puzzles:
+  coins                   the order to put the coins into the monument in, worked out from
+                          the equation on it and the coins in the inventory
+  orb                     the shortest walk through the vault lock that gets the orb to the
+                          door at the right weight, mapped from the antechamber
+
+options:
+  --script <file>         play the file first; it has to end where the puzzle is
+  --hack-teleporter       skip the teleporter's check of r7, for scripts that set it
+  --grid <file>           solve the orb puzzle for a grid written down in a file instead,
+                          a row of cells per line and then \"= <target>\", e.g.
+                            *  8  -  1
+                            4  * 11  *
+                            +  4  - 18
+                           22  -  9  *
+                           = 30";
+
+const CODES_USAGE: &str = "\
+usage: vmc codes [--hashes <file>] [<transcript>]
+
+Lists the codes in <transcript>, or in stdin without one, e.g. `vmc run | vmc codes`. Codes
+seen in a mirror are also shown the right way round. Each is checked against the MD5 hashes
+of the valid codes.
+
+options:
+  --hashes <file>         MD5 hashes of the valid codes, one per line [default: codes.txt]";
+
+const STATE_USAGE: &str = "\
+usage: vmc state [--script <file> [--hack-teleporter]]
+
+Reads the current room, the inventory and where each item is out of the game's memory,
+using the [struct item], [struct room] and [enum location] layout from the annotations.
+
+options:
+  --script <file>         play the file first, and show the state where it ends
+  --hack-teleporter       skip the teleporter's check of r7, for scripts that set it";
+
+pub struct Cli {
+    pub rom: PathBuf,
+    /// Explicitly given annotations file. Without one, `annotations.ini` is used if it exists.
+    pub annotations: Option<PathBuf>,
+    pub command: Command,
+}
+
+pub enum Command {
+    Run(RunOptions),
+    Decompile {
+        high_level: bool,
+        options: Options,
+    },
+    Annotate(Annotate),
+    Reg8,
+    Info,
+    Functions {
+        export: bool,
+    },
+    Scan {
+        pattern: Option<String>,
+        label: bool,
+    },
+    Xref {
+        target: String,
+        boot: bool,
+    },
+    Explore {
+        play: PlayOptions,
+        format: MapFormat,
+    },
+    Route {
+        play: PlayOptions,
+        from: String,
+        to: String,
+    },
+    Solve {
+        puzzle: Puzzle,
+        play: PlayOptions,
+        grid: Option<PathBuf>,
+    },
+    Codes {
+        transcript: Option<PathBuf>,
+        hashes: Option<PathBuf>,
+    },
+    State {
+        play: PlayOptions,
+    },
+    /// Print the usage text.
+    Help(&'static str),
+}
+
+#[derive(Default)]
+pub struct RunOptions {
+    pub script: Option<PathBuf>,
+    pub trace_out: Option<PathBuf>,
+    pub max_steps: Option<u64>,
+    pub mode: Mode,
+    pub hack_teleporter: bool,
+    pub meta_prefix: Option<String>,
+}
+
+/// How the commands that look at the game get it to where they start.
+pub struct PlayOptions {
+    pub script: Option<PathBuf>,
+    pub hack_teleporter: bool,
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum MapFormat {
+    #[default]
+    Json,
+    Dot,
+}
+
+#[derive(Clone, Copy)]
+pub enum Puzzle {
+    Coins,
+    Orb,
+}
+
+pub enum Annotate {
+    Label(u16, String),
+    Comment(u16, String),
+    Auto,
+}
+
+#[derive(Debug)]
+pub struct CliError {
+    pub message: String,
+    pub usage: &'static str,
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n\n{}", self.message, self.usage)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// The commands besides `run` that play the game to where they start, see [`PlayOptions`].
+const PLAY_COMMANDS: [&str; 4] = ["explore", "route", "solve", "state"];
+
+fn usage_for(command: &str) -> Option<&'static str> {
+    Some(match command {
+        "run" => RUN_USAGE,
+        "decompile" => DECOMPILE_USAGE,
+        "annotate" => ANNOTATE_USAGE,
+        "reg8" => REG8_USAGE,
+        "info" => INFO_USAGE,
+        "scan" => SCAN_USAGE,
+        "functions" => FUNCTIONS_USAGE,
+        "xref" => XREF_USAGE,
+        "explore" => EXPLORE_USAGE,
+        "route" => ROUTE_USAGE,
+        "solve" => SOLVE_USAGE,
+        "codes" => CODES_USAGE,
+        "state" => STATE_USAGE,
+        "help" => USAGE,
+        _ => return None,
+    })
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, CliError> {
+    let mut args = args.into_iter().peekable();
+    let command = match args.peek() {
+        Some(a) if !a.starts_with('-') => args.next().unwrap(),
+        _ => "run".to_owned(),
+    };
+    let Some(usage) = usage_for(&command) else {
+        return Err(CliError {
+            message: format!("unknown command {command:?}"),
+            usage: USAGE,
+        });
+    };
+    let err = |message: String| CliError { message, usage };
+
+    let mut cli = Cli {
+        rom: PathBuf::from("challenge.bin"),
+        annotations: None,
+        command: Command::Help(usage),
+    };
+    let mut run = RunOptions::default();
+    let mut modes = Vec::new();
+    let mut label = false;
+    let mut export = false;
+    let mut high_level = false;
+    let mut boot = false;
+    let mut format = MapFormat::default();
+    let mut grid = None;
+    let mut hashes = None;
+    let mut decompile = Options::default();
+    let mut positional = Vec::new();
+    let plays = command == "run" || PLAY_COMMANDS.contains(&command.as_str());
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| err(format!("{name} needs a value")))
+        };
+        let is_run = command == "run";
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(cli),
+            "--rom" => cli.rom = value(&arg)?.into(),
+            "--annotations" => cli.annotations = Some(value(&arg)?.into()),
+            "--script" if plays => run.script = Some(value(&arg)?.into()),
+            "--trace" if is_run => run.trace_out = Some("run.trace".into()),
+            "--trace-out" if is_run => run.trace_out = Some(value(&arg)?.into()),
+            "--max-steps" if is_run => {
+                let n = value(&arg)?;
+                run.max_steps = Some(
+                    n.parse()
+                        .map_err(|e| err(format!("invalid --max-steps {n:?}: {e}")))?,
+                );
+            }
+            "--strict" if is_run => modes.push(Mode::Strict),
+            "--lenient" if is_run => modes.push(Mode::Lenient),
+            "--hack-teleporter" if plays => run.hack_teleporter = true,
+            "--meta-prefix" if is_run => run.meta_prefix = Some(value(&arg)?),
+            "--label" if command == "scan" => label = true,
+            "--export" if command == "functions" => export = true,
+            "--boot" if command == "xref" => boot = true,
+            "--grid" if command == "solve" => grid = Some(value(&arg)?.into()),
+            "--hashes" if command == "codes" => hashes = Some(value(&arg)?.into()),
+            "--format" if command == "explore" => {
+                format = match value(&arg)?.as_str() {
+                    "json" => MapFormat::Json,
+                    "dot" => MapFormat::Dot,
+                    f => return Err(err(format!("unknown format {f:?}"))),
+                }
+            }
+            "--high-level" if command == "decompile" => high_level = true,
+            "--idioms" if command == "decompile" => {
+                decompile.idioms = Idioms::parse(&value(&arg)?).map_err(err)?;
+            }
+            "--values" if command == "decompile" => decompile.values = true,
+            a if a.starts_with('-') => return Err(err(format!("unknown option {a:?}"))),
+            _ => positional.push(arg),
+        }
+    }
+    if modes.len() > 1 {
+        return Err(err(
+            "--strict and --lenient are mutually exclusive".to_owned()
+        ));
+    }
+    run.mode = modes.pop().unwrap_or_default();
+    if run.hack_teleporter && run.script.is_none() && command != "run" {
+        return Err(err(
+            "--hack-teleporter only applies with --script".to_owned()
+        ));
+    }
+    let play = || PlayOptions {
+        script: run.script.clone(),
+        hack_teleporter: run.hack_teleporter,
+    };
+
+    let too_many = |n: usize| {
+        positional
+            .get(n)
+            .map_or(Ok(()), |p| Err(err(format!("unexpected argument {p:?}"))))
+    };
+    cli.command = match command.as_str() {
+        "run" => {
+            too_many(0)?;
+            Command::Run(run)
+        }
+        "decompile" => {
+            too_many(0)?;
+            Command::Decompile {
+                high_level,
+                options: decompile,
+            }
+        }
+        "reg8" => {
+            too_many(0)?;
+            Command::Reg8
+        }
+        "info" => {
+            too_many(0)?;
+            Command::Info
+        }
+        "functions" => {
+            too_many(0)?;
+            Command::Functions { export }
+        }
+        "scan" => {
+            let pattern = (!positional.is_empty()).then(|| positional.join(" "));
+            if label && pattern.is_some() {
+                return Err(err("--label only applies to the known routines".to_owned()));
+            }
+            Command::Scan { pattern, label }
+        }
+        "xref" => {
+            too_many(1)?;
+            let target = positional
+                .pop()
+                .ok_or_else(|| err("missing <addr|name>".to_owned()))?;
+            Command::Xref { target, boot }
+        }
+        "explore" => {
+            too_many(0)?;
+            Command::Explore {
+                play: play(),
+                format,
+            }
+        }
+        "route" => {
+            too_many(2)?;
+            let [from, to] = <[String; 2]>::try_from(positional)
+                .map_err(|_| err("missing <from> or <to>".to_owned()))?;
+            Command::Route {
+                play: play(),
+                from,
+                to,
+            }
+        }
+        "solve" => {
+            too_many(1)?;
+            let puzzle = match positional.first().map(String::as_str) {
+                Some("coins") => Puzzle::Coins,
+                Some("orb") => Puzzle::Orb,
+                Some(p) => return Err(err(format!("unknown puzzle {p:?}"))),
+                None => return Err(err("missing puzzle".to_owned())),
+            };
+            if grid.is_some() && !matches!(puzzle, Puzzle::Orb) {
+                return Err(err("--grid only applies to the orb".to_owned()));
+            }
+            if grid.is_some() && run.script.is_some() {
+                return Err(err("--script and --grid are mutually exclusive".to_owned()));
+            }
+            Command::Solve {
+                puzzle,
+                play: play(),
+                grid,
+            }
+        }
+        "codes" => {
+            too_many(1)?;
+            Command::Codes {
+                transcript: positional.pop().map(PathBuf::from),
+                hashes,
+            }
+        }
+        "state" => {
+            too_many(0)?;
+            Command::State { play: play() }
+        }
+        "help" => {
+            too_many(1)?;
+            match positional.first() {
+                None => Command::Help(USAGE),
+                Some(c) => Command::Help(
+                    usage_for(c).ok_or_else(|| err(format!("unknown command {c:?}")))?,
+                ),
+            }
+        }
+        "annotate" => {
+            let addr = |s: Option<&String>| {
+                let s = s.ok_or_else(|| err("missing <addr>".to_owned()))?;
+                u16::from_str_radix(s.trim_start_matches("0x"), 16)
+                    .map_err(|e| err(format!("invalid address {s:?}: {e}")))
+            };
+            match positional.first().map(String::as_str) {
+                Some("label") => {
+                    too_many(3)?;
+                    let name = positional
+                        .get(2)
+                        .ok_or_else(|| err("missing <name>".to_owned()))?;
+                    Command::Annotate(Annotate::Label(addr(positional.get(1))?, name.clone()))
+                }
+                Some("comment") => {
+                    let at = addr(positional.get(1))?;
+                    if positional.len() < 3 {
+                        return Err(err("missing <text>".to_owned()));
+                    }
+                    Command::Annotate(Annotate::Comment(at, positional[2..].join(" ")))
+                }
+                Some("auto") => {
+                    too_many(1)?;
+                    Command::Annotate(Annotate::Auto)
+                }
+                Some(a) => return Err(err(format!("unknown annotate action {a:?}"))),
+                None => return Err(err("missing action".to_owned())),
+            }
+        }
+        _ => unreachable!("checked by usage_for"),
+    };
+    Ok(cli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(args: &str) -> Result<Cli, CliError> {
+        parse(args.split_whitespace().map(str::to_owned))
+    }
+
+    fn command(args: &str) -> Command {
+        parse_str(args)
+            .unwrap_or_else(|e| panic!("{args:?}: {e}"))
+            .command
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            ("frobnicate", USAGE, "unknown command \"frobnicate\""),
+            ("run --bogus", RUN_USAGE, "unknown option \"--bogus\""),
+            (
+                "decompile --trace",
+                DECOMPILE_USAGE,
+                "unknown option \"--trace\"",
+            ),
+            ("--script", RUN_USAGE, "--script needs a value"),
+            ("solve orb --grid", SOLVE_USAGE, "--grid needs a value"),
+            (
+                "run --max-steps lots",
+                RUN_USAGE,
+                "invalid --max-steps \"lots\": invalid digit found in string",
+            ),
+            (
+                "--strict --lenient",
+                RUN_USAGE,
+                "--strict and --lenient are mutually exclusive",
+            ),
+            (
+                "state --hack-teleporter",
+                STATE_USAGE,
+                "--hack-teleporter only applies with --script",
+            ),
+            ("route here", ROUTE_USAGE, "missing <from> or <to>"),
+            ("info extra", INFO_USAGE, "unexpected argument \"extra\""),
+            (
+                "annotate label zz x",
+                ANNOTATE_USAGE,
+                "invalid address \"zz\"",
+            ),
+        ];
+        for (args, usage, message) in cases {
+            let Err(err) = parse_str(args) else {
+                panic!("{args:?} parsed");
+            };
+            assert!(err.message.starts_with(message), "{args:?}: {err}");
+            assert_eq!(err.usage, usage, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let cli = parse_str("").unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(cli.rom, PathBuf::from("challenge.bin"));
+        assert_eq!(cli.annotations, None);
+        let Command::Run(run) = cli.command else {
+            panic!("not run");
+        };
+        assert_eq!(run.script, None);
+        assert_eq!(run.trace_out, None);
+        assert_eq!(run.max_steps, None);
+        assert_eq!(run.mode, Mode::default());
+        assert!(!run.hack_teleporter);
+        assert_eq!(run.meta_prefix, None);
+
+        let Command::Decompile {
+            high_level,
+            options,
+        } = command("decompile")
+        else {
+            panic!("not decompile");
+        };
+        assert!(!high_level);
+        assert_eq!(options.idioms, Idioms::default());
+        assert!(!options.values);
+
+        let no_play = |play: &PlayOptions| play.script.is_none() && !play.hack_teleporter;
+        assert!(matches!(command("run"), Command::Run(_)));
+        assert!(matches!(
+            command("annotate auto"),
+            Command::Annotate(Annotate::Auto)
+        ));
+        assert!(matches!(command("reg8"), Command::Reg8));
+        assert!(matches!(command("info"), Command::Info));
+        assert!(matches!(
+            command("functions"),
+            Command::Functions { export: false }
+        ));
+        assert!(matches!(
+            command("scan"),
+            Command::Scan {
+                pattern: None,
+                label: false
+            }
+        ));
+        assert!(matches!(
+            command("xref 0x10"),
+            Command::Xref { target, boot: false } if target == "0x10"
+        ));
+        assert!(matches!(
+            command("explore"),
+            Command::Explore { play, format: MapFormat::Json } if no_play(&play)
+        ));
+        assert!(matches!(
+            command("route here there"),
+            Command::Route { play, from, to } if no_play(&play) && from == "here" && to == "there"
+        ));
+        assert!(matches!(
+            command("solve coins"),
+            Command::Solve { puzzle: Puzzle::Coins, play, grid: None } if no_play(&play)
+        ));
+        assert!(matches!(
+            command("codes"),
+            Command::Codes {
+                transcript: None,
+                hashes: None
+            }
+        ));
+        assert!(matches!(command("state"), Command::State { play } if no_play(&play)));
+        assert!(matches!(command("help"), Command::Help(USAGE)));
+        assert!(matches!(command("help route"), Command::Help(ROUTE_USAGE)));
+        assert!(matches!(
+            command("route --help"),
+            Command::Help(ROUTE_USAGE)
+        ));
+    }
+}
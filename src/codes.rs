@@ -0,0 +1,203 @@
+//! Picking the codes out of the game's output and checking them against `codes.txt`, which
+//! holds the MD5 of each valid code, one per line in hex.
+//!
+//! One code is seen in a mirror, so it's printed back to front with its glyphs flipped; the
+//! code to enter is its mirror image.
+
+use std::collections::HashSet;
+
+/// A code as printed, and where.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Found {
+    pub code: String,
+    /// 1-based line of the output it's on.
+    pub line: usize,
+    /// What the code reads as the right way round, if it was seen in a mirror.
+    pub mirrored: Option<String>,
+}
+
+impl Found {
+    /// The code as printed, then its mirror image if it has one.
+    pub fn candidates(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.code.as_str()).chain(self.mirrored.as_deref())
+    }
+}
+
+/// Whether `word` looks like a code rather than English: 10 to 12 letters and digits with an
+/// upper case letter somewhere other than the start, and a lower case one.
+fn is_code(word: &str) -> bool {
+    (10..=12).contains(&word.len())
+        && word.chars().all(|c| c.is_ascii_alphanumeric())
+        && word.chars().skip(1).any(|c| c.is_ascii_uppercase())
+        && word.chars().any(|c| c.is_ascii_lowercase())
+}
+
+/// Every code in `output`, in order. A code on a line that mentions a mirror is taken to be
+/// mirrored.
+pub fn find(output: &str) -> Vec<Found> {
+    let mut found = Vec::new();
+    for (i, line) in output.lines().enumerate() {
+        let words = line.split(|c: char| c.is_whitespace() || "\"':.,!?".contains(c));
+        for word in words.filter(|w| is_code(w)) {
+            found.push(Found {
+                code: word.to_owned(),
+                line: i + 1,
+                mirrored: line.contains("mirror").then(|| mirror(word)),
+            });
+        }
+    }
+    found
+}
+
+/// `code` as it reads in a mirror: back to front, with the glyphs that turn into each other
+/// swapped.
+pub fn mirror(code: &str) -> String {
+    code.chars()
+        .rev()
+        .map(|c| match c {
+            'b' => 'd',
+            'd' => 'b',
+            'p' => 'q',
+            'q' => 'p',
+            c => c,
+        })
+        .collect()
+}
+
+/// The MD5 hashes of the valid codes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Hashes(HashSet<[u8; 16]>);
+
+impl std::str::FromStr for Hashes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                let invalid = || format!("invalid MD5 {l:?}");
+                if l.len() != 32 || !l.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(invalid());
+                }
+                let mut hash = [0; 16];
+                for (i, b) in hash.iter_mut().enumerate() {
+                    *b = u8::from_str_radix(&l[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+                }
+                Ok(hash)
+            })
+            .collect::<Result<_, _>>()
+            .map(Hashes)
+    }
+}
+
+impl Hashes {
+    pub fn contains(&self, code: &str) -> bool {
+        self.0.contains(&md5(code.as_bytes()))
+    }
+
+    /// The first of `found`'s candidates that is a valid code.
+    pub fn check<'a>(&self, found: &'a Found) -> Option<&'a str> {
+        found.candidates().find(|c| self.contains(c))
+    }
+}
+
+/// RFC 1321.
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    const S: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+        9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10,
+        15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    // floor(abs(sin(i + 1)) * 2^32)
+    let k: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32)
+        .collect();
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    for chunk in message.chunks(64) {
+        let m: Vec<u32> = chunk
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(k[i]).wrapping_add(m[g]);
+            (a, d, c) = (d, c, b);
+            b = b.wrapping_add(f.rotate_left(S[i]));
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+    let mut digest = [0; 16];
+    for (i, s) in state.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&s.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(hash: [u8; 16]) -> String {
+        hash.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn md5_matches_reference() {
+        assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            hex(md5(b"The quick brown fox jumps over the lazy dog")),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        assert_eq!(hex(md5(&[b'a'; 100])), "36a92cc94a9e0fa21f625f8bfb007adf");
+    }
+
+    #[test]
+    fn finds_mirrored_codes() {
+        let output = "\
+Welcome to the Headquarters!
+this one into the challenge website: ImoFztWQCvxj
+
+Through the mirror, you see \"iW8UwOHpH8op\" scrawled in charcoal on your forehead.
+";
+        let found = find(output);
+        assert_eq!(
+            found,
+            [
+                Found {
+                    code: "ImoFztWQCvxj".to_owned(),
+                    line: 2,
+                    mirrored: None,
+                },
+                Found {
+                    code: "iW8UwOHpH8op".to_owned(),
+                    line: 4,
+                    mirrored: Some("qo8HqHOwU8Wi".to_owned()),
+                },
+            ]
+        );
+
+        let hashes: Hashes = std::fs::read_to_string("codes.txt")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(hashes.check(&found[0]), Some("ImoFztWQCvxj"));
+        assert_eq!(hashes.check(&found[1]), Some("qo8HqHOwU8Wi"));
+        assert!("abc".parse::<Hashes>().is_err());
+    }
+}
@@ -0,0 +1,243 @@
+//! The coin puzzle in the ruins: the coins go into the monument's slots in the order that
+//! makes the equation on it true. Each coin is worth the number of dots or sides on it.
+
+use crate::machine::{Buffer, Machine};
+
+/// The monument's equation, e.g. `_ + _ * _^2 + _^3 - _ = 399`, with a slot per `_`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Equation {
+    text: String,
+    /// The operator before each slot, `+` for the first, and the power the slot is raised to.
+    slots: Vec<(char, u32)>,
+    target: i64,
+}
+
+impl std::str::FromStr for Equation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (lhs, target) = s
+            .split_once('=')
+            .ok_or_else(|| format!("no '=' in {s:?}"))?;
+        let target = target
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid target {:?}: {e}", target.trim()))?;
+        let mut slots = Vec::new();
+        let mut op = '+';
+        for (i, token) in lhs.split_whitespace().enumerate() {
+            if i % 2 == 1 {
+                op = match token {
+                    "+" | "-" | "*" => token.chars().next().unwrap(),
+                    _ => return Err(format!("expected an operator, got {token:?}")),
+                };
+                continue;
+            }
+            let power = match token.strip_prefix('_') {
+                Some("") => 1,
+                Some(p) => p
+                    .strip_prefix('^')
+                    .and_then(|p| p.parse().ok())
+                    .ok_or_else(|| format!("invalid slot {token:?}"))?,
+                None => return Err(format!("expected a slot, got {token:?}")),
+            };
+            slots.push((op, power));
+        }
+        if lhs.split_whitespace().count() % 2 == 0 {
+            return Err(format!("{s:?} doesn't end in a slot"));
+        }
+        Ok(Equation {
+            text: s.trim().to_owned(),
+            slots,
+            target,
+        })
+    }
+}
+
+impl std::fmt::Display for Equation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Equation {
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Whether the equation holds with `values` in the slots, `*` binding tighter than `+`
+    /// and `-`. It doesn't if working it out overflows.
+    pub fn holds(&self, values: &[i64]) -> bool {
+        self.eval(values) == Some(self.target)
+    }
+
+    /// The left-hand side with `values` in the slots, or `None` on overflow.
+    fn eval(&self, values: &[i64]) -> Option<i64> {
+        let (mut sum, mut term) = (0i64, 0i64);
+        for (&(op, power), &v) in self.slots.iter().zip(values) {
+            let v = v.checked_pow(power)?;
+            match op {
+                '*' => term = term.checked_mul(v)?,
+                '-' => (sum, term) = (sum.checked_add(term)?, v.checked_neg()?),
+                _ => (sum, term) = (sum.checked_add(term)?, v),
+            }
+        }
+        sum.checked_add(term)
+    }
+
+    /// The equation with `values` written into the slots.
+    pub fn fill(&self, values: &[i64]) -> String {
+        let mut values = values.iter();
+        self.text
+            .chars()
+            .map(|c| match c {
+                '_' => values.next().map_or("_".to_owned(), |v| v.to_string()),
+                c => c.to_string(),
+            })
+            .collect()
+    }
+}
+
+/// What a coin is worth, from its description: "It has two dots on one side." is 2 and "It
+/// has a pentagon on one side." is 5.
+pub fn value(description: &str) -> Option<i64> {
+    const NUMBERS: [&str; 9] = [
+        "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+    ];
+    const SHAPES: [&str; 7] = [
+        "triangle", "square", "pentagon", "hexagon", "heptagon", "octagon", "nonagon",
+    ];
+    let (_, side) = description.split_once("It has ")?;
+    let (marking, _) = side.split_once(" on one side")?;
+    let words: Vec<&str> = marking.split_whitespace().collect();
+    match words[..] {
+        [n, "dot" | "dots"] => NUMBERS.iter().position(|w| *w == n).map(|i| i as i64 + 1),
+        ["a" | "an", shape] => SHAPES
+            .iter()
+            .position(|w| *w == shape)
+            .map(|i| i as i64 + 3),
+        _ => None,
+    }
+}
+
+/// The coins, in slot order, that make `equation` hold.
+pub fn solve<'a>(
+    equation: &Equation,
+    coins: &'a [(String, i64)],
+) -> Option<Vec<&'a (String, i64)>> {
+    fn place<'a>(
+        equation: &Equation,
+        coins: &'a [(String, i64)],
+        used: &mut Vec<usize>,
+    ) -> Option<Vec<&'a (String, i64)>> {
+        if used.len() == equation.slots() {
+            let values: Vec<_> = used.iter().map(|&i| coins[i].1).collect();
+            return equation
+                .holds(&values)
+                .then(|| used.iter().map(|&i| &coins[i]).collect());
+        }
+        for i in 0..coins.len() {
+            if used.contains(&i) {
+                continue;
+            }
+            used.push(i);
+            if let Some(order) = place(equation, coins, used) {
+                return Some(order);
+            }
+            used.pop();
+        }
+        None
+    }
+    place(equation, coins, &mut Vec::new())
+}
+
+/// Reads the puzzle from the game, which must be waiting for input by the monument with the
+/// coins in the inventory: the equation from `look`, and each coin's value from `look <coin>`.
+pub fn read(machine: &mut Machine<Buffer>) -> Result<(Equation, Vec<(String, i64)>), String> {
+    let mut command = |line: &str| machine.command(line).map_err(|e| format!("{line}: {e}"));
+    let room = command("look")?;
+    let equation = room
+        .lines()
+        .find(|l| l.contains('_') && l.contains('='))
+        .ok_or("no equation in sight; play up to the monument first")?
+        .parse()?;
+    let inventory = command("inv")?;
+    let names: Vec<_> = inventory
+        .lines()
+        .filter_map(|l| l.strip_prefix("- "))
+        .filter(|item| item.ends_with(" coin"))
+        .map(str::to_owned)
+        .collect();
+    if names.is_empty() {
+        return Err("no coins in the inventory".to_owned());
+    }
+    let mut coins = Vec::new();
+    for name in names {
+        let description = command(&format!("look {name}"))?;
+        let value = value(&description)
+            .ok_or_else(|| format!("can't tell what the {name} is worth: {description:?}"))?;
+        coins.push((name, value));
+    }
+    Ok((equation, coins))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{error::Error, machine::load_rom};
+
+    const MONUMENT: &str = "_ + _ * _^2 + _^3 - _ = 399";
+
+    #[test]
+    fn evaluates_equation() {
+        let equation: Equation = MONUMENT.parse().unwrap();
+        assert_eq!(equation.slots(), 5);
+        assert!(equation.holds(&[9, 2, 5, 7, 3]));
+        assert!(!equation.holds(&[2, 9, 5, 7, 3]));
+        assert_eq!(
+            equation.fill(&[9, 2, 5, 7, 3]),
+            "9 + 2 * 5^2 + 7^3 - 3 = 399"
+        );
+        assert!("_ + = 3".parse::<Equation>().is_err());
+        assert!("_ + _^x = 3".parse::<Equation>().is_err());
+        let huge: Equation = "_^4000000000 = 0".parse().unwrap();
+        assert!(!huge.holds(&[2]));
+        assert!(huge.holds(&[0]));
+    }
+
+    #[test]
+    fn values_coins() {
+        assert_eq!(value("It has two dots on one side."), Some(2));
+        assert_eq!(value("Shiny.  It has a pentagon on one side."), Some(5));
+        assert_eq!(value("It has a triangle on one side."), Some(3));
+        assert_eq!(value("It has a smiley on one side."), None);
+    }
+
+    #[test]
+    fn solves_the_monument() {
+        let mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let mut machine = Machine::with_console(mem, Buffer::default());
+        // script.txt up to, not including, the first coin going in.
+        let script = std::fs::read_to_string("script.txt").unwrap();
+        let end = script.find("use blue coin").unwrap();
+        machine.set_script(script[..end].parse().unwrap());
+        assert!(matches!(machine.run(), Err(Error::InputExhausted(_))));
+
+        let (equation, coins) = read(&mut machine).unwrap();
+        assert_eq!(equation.to_string(), MONUMENT);
+        let order = solve(&equation, &coins).unwrap();
+        let names: Vec<_> = order.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "blue coin",
+                "red coin",
+                "shiny coin",
+                "concave coin",
+                "corroded coin"
+            ]
+        );
+    }
+}
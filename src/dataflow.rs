@@ -0,0 +1,399 @@
+//! What each register can hold before each op: reaching definitions and constant values.
+//!
+//! Values are tracked as small sets, so that a register set differently on two paths, or by
+//! two callers, is still known. Values flow into functions from their literal call sites and
+//! from the indirect calls that can be resolved.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    functions::{Function, Functions, regs, successors},
+    machine::{MAX_U15, MOD},
+    op::{Op, Reg, Val},
+};
+
+/// More possible values than this and a register is unknown.
+const MAX_VALUES: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Unknown,
+    /// One of these values.
+    Known(BTreeSet<u16>),
+}
+
+impl Value {
+    fn known(values: impl IntoIterator<Item = u16>) -> Value {
+        let set: BTreeSet<u16> = values.into_iter().collect();
+        match set.len() {
+            1..=MAX_VALUES => Value::Known(set),
+            _ => Value::Unknown,
+        }
+    }
+
+    fn join(&self, other: &Value) -> Value {
+        match (self, other) {
+            (Value::Known(a), Value::Known(b)) => Value::known(a.union(b).copied()),
+            _ => Value::Unknown,
+        }
+    }
+
+    /// The single value, if there is only one.
+    pub fn constant(&self) -> Option<u16> {
+        match self {
+            Value::Known(set) if set.len() == 1 => set.first().copied(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Unknown => write!(f, "?"),
+            Value::Known(set) => {
+                let values: Vec<_> = set.iter().map(|v| format!("0x{v:04x}")).collect();
+                write!(f, "{}", values.join(" | "))
+            }
+        }
+    }
+}
+
+/// Where a register's value came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Def {
+    /// Set before the function was entered.
+    Entry,
+    /// Set by the op at this address, or by the function it calls.
+    At(u16),
+}
+
+/// The state before an op.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct State {
+    pub values: [Value; 8],
+    pub defs: [BTreeSet<Def>; 8],
+}
+
+impl State {
+    fn entry(values: [Value; 8]) -> State {
+        State {
+            values,
+            defs: std::array::from_fn(|_| [Def::Entry].into()),
+        }
+    }
+
+    fn join(&self, other: &State) -> State {
+        State {
+            values: std::array::from_fn(|i| self.values[i].join(&other.values[i])),
+            defs: std::array::from_fn(|i| self.defs[i].union(&other.defs[i]).copied().collect()),
+        }
+    }
+
+    fn value(&self, v: Val) -> Value {
+        match v {
+            Val::Literal(x) => Value::known([x]),
+            Val::Reg(r) => self.values[r.index()].clone(),
+        }
+    }
+}
+
+fn unknown() -> [Value; 8] {
+    std::array::from_fn(|_| Value::Unknown)
+}
+
+fn binary(a: &Value, b: &Value, f: impl Fn(u32, u32) -> u32) -> Value {
+    match (a, b) {
+        (Value::Known(a), Value::Known(b)) => Value::known(
+            a.iter()
+                .flat_map(|&x| b.iter().map(move |&y| (x, y)))
+                .map(|(x, y)| (f(x as u32, y as u32) % MOD as u32) as u16),
+        ),
+        _ => Value::Unknown,
+    }
+}
+
+pub struct Dataflow {
+    /// The state before each op, joined over every function the op is part of.
+    pub states: HashMap<u16, State>,
+}
+
+impl Dataflow {
+    pub fn analyze(funcs: &Functions) -> Dataflow {
+        let mut this = Dataflow {
+            states: HashMap::new(),
+        };
+        // Values on entry to each function, from its callers. Functions nobody is known to
+        // call start out unknown.
+        let mut entries: HashMap<u16, [Value; 8]> = HashMap::new();
+        let called: BTreeSet<u16> = funcs
+            .ops
+            .values()
+            .filter_map(|op| match op {
+                Op::Call(Val::Literal(to)) => Some(*to),
+                _ => None,
+            })
+            .collect();
+        for f in funcs.functions.values() {
+            if f.entry == 0 || !called.contains(&f.entry) {
+                entries.insert(f.entry, unknown());
+            }
+        }
+        // Bounded, in case indirect call targets keep the entries from settling.
+        for _ in 0..1024 {
+            let mut states = HashMap::new();
+            for f in funcs.functions.values() {
+                if let Some(values) = entries.get(&f.entry) {
+                    for (addr, state) in this.function(funcs, f, State::entry(values.clone())) {
+                        join_into(&mut states, addr, state);
+                    }
+                }
+            }
+            this.states = states;
+            let mut next = entries.clone();
+            for (addr, state) in &this.states {
+                let targets = match funcs.ops[addr] {
+                    Op::Call(to) => this.targets_of(*addr, to),
+                    _ => continue,
+                };
+                for to in targets.into_iter().flatten() {
+                    if funcs.functions.contains_key(&to) {
+                        let values = match next.get(&to) {
+                            Some(v) => std::array::from_fn(|i| v[i].join(&state.values[i])),
+                            None => state.values.clone(),
+                        };
+                        next.insert(to, values);
+                    }
+                }
+            }
+            if next == entries {
+                // Whatever calls the functions still not reached is only reached indirectly,
+                // so start the outermost of them out unknown.
+                let missing: Vec<&Function> = funcs
+                    .functions
+                    .values()
+                    .filter(|f| !entries.contains_key(&f.entry))
+                    .collect();
+                if missing.is_empty() {
+                    break;
+                }
+                let roots: Vec<u16> = missing
+                    .iter()
+                    .filter(|f| {
+                        !missing.iter().any(|caller| {
+                            caller
+                                .body
+                                .iter()
+                                .any(|a| funcs.ops[a] == Op::Call(Val::Literal(f.entry)))
+                        })
+                    })
+                    .map(|f| f.entry)
+                    .collect();
+                let roots = if roots.is_empty() {
+                    missing.iter().map(|f| f.entry).collect()
+                } else {
+                    roots
+                };
+                for entry in roots {
+                    next.insert(entry, unknown());
+                }
+            }
+            entries = next;
+        }
+        this
+    }
+
+    /// Runs `f` forward from `entry`, returning the state before each of its ops.
+    fn function(&self, funcs: &Functions, f: &Function, entry: State) -> HashMap<u16, State> {
+        let mut states = HashMap::new();
+        states.insert(f.entry, entry);
+        let mut todo = vec![f.entry];
+        while let Some(addr) = todo.pop() {
+            let op = funcs.ops[&addr];
+            let after = self.transfer(funcs, addr, &op, &states[&addr]);
+            for next in successors(addr, &op) {
+                if !funcs.ops.contains_key(&next) {
+                    continue;
+                }
+                let joined = match states.get(&next) {
+                    Some(s) => s.join(&after),
+                    None => after.clone(),
+                };
+                if states.get(&next) != Some(&joined) {
+                    states.insert(next, joined);
+                    todo.push(next);
+                }
+            }
+        }
+        states
+    }
+
+    fn transfer(&self, funcs: &Functions, addr: u16, op: &Op, s: &State) -> State {
+        let mut out = s.clone();
+        let mut set = |r: Reg, v: Value| {
+            out.values[r.index()] = v;
+            out.defs[r.index()] = [Def::At(addr)].into();
+        };
+        match *op {
+            Op::Set(a, b) => set(a, s.value(b)),
+            Op::Eq(a, b, c) => set(a, binary(&s.value(b), &s.value(c), |x, y| (x == y) as u32)),
+            Op::Gt(a, b, c) => set(a, binary(&s.value(b), &s.value(c), |x, y| (x > y) as u32)),
+            Op::Add(a, b, c) => set(a, binary(&s.value(b), &s.value(c), |x, y| x + y)),
+            Op::Mult(a, b, c) => set(a, binary(&s.value(b), &s.value(c), |x, y| x * y)),
+            Op::Mod(a, b, c) => set(
+                a,
+                binary(&s.value(b), &s.value(c), |x, y| {
+                    x.checked_rem(y).unwrap_or(0)
+                }),
+            ),
+            Op::And(a, b, c) => set(a, binary(&s.value(b), &s.value(c), |x, y| x & y)),
+            Op::Or(a, b, c) => set(a, binary(&s.value(b), &s.value(c), |x, y| x | y)),
+            Op::Not(a, b) => set(
+                a,
+                binary(&s.value(b), &Value::known([0]), |x, _| !x & MAX_U15 as u32),
+            ),
+            Op::Rmem(a, _) | Op::Pop(a) | Op::In(a) => set(a, Value::Unknown),
+            Op::Call(to) => {
+                let modified = match self.targets_of(addr, to) {
+                    Some(targets) => targets
+                        .iter()
+                        .map(|t| funcs.functions.get(t).map_or(0xff, |c| c.modified))
+                        .fold(0, |a, b| a | b),
+                    None => 0xff,
+                };
+                for r in regs(modified) {
+                    set(r, Value::Unknown);
+                }
+            }
+            _ => {}
+        }
+        out
+    }
+
+    /// The addresses a `call`, `jmp`, `jt` or `jf` at `addr` with target `to` can go to, or
+    /// `None` if they aren't known.
+    fn targets_of(&self, addr: u16, to: Val) -> Option<BTreeSet<u16>> {
+        match to {
+            Val::Literal(t) => Some([t].into()),
+            Val::Reg(r) => match &self.states.get(&addr)?.values[r.index()] {
+                Value::Known(set) => Some(set.clone()),
+                Value::Unknown => None,
+            },
+        }
+    }
+
+    /// Where the indirect `call` or jump at `addr` can go, if that's known.
+    pub fn resolve(&self, addr: u16, op: &Op) -> Option<BTreeSet<u16>> {
+        match *op {
+            Op::Call(to @ Val::Reg(_))
+            | Op::Jmp(to @ Val::Reg(_))
+            | Op::Jt(_, to @ Val::Reg(_))
+            | Op::Jf(_, to @ Val::Reg(_)) => self.targets_of(addr, to),
+            _ => None,
+        }
+    }
+
+    /// Known values of the registers `op` reads, in operand order.
+    pub fn known_reads(&self, addr: u16, op: &Op) -> Vec<(Reg, Value)> {
+        let Some(state) = self.states.get(&addr) else {
+            return Vec::new();
+        };
+        let mut seen = BTreeSet::new();
+        reads(op)
+            .into_iter()
+            .filter_map(|v| match v {
+                Val::Reg(r) if seen.insert(r.index()) => Some(r),
+                _ => None,
+            })
+            .filter_map(|r| match &state.values[r.index()] {
+                Value::Unknown => None,
+                v => Some((r, v.clone())),
+            })
+            .collect()
+    }
+}
+
+fn join_into(states: &mut HashMap<u16, State>, addr: u16, state: State) {
+    let joined = match states.get(&addr) {
+        Some(s) => s.join(&state),
+        None => state,
+    };
+    states.insert(addr, joined);
+}
+
+/// The operands `op` reads.
+fn reads(op: &Op) -> Vec<Val> {
+    match *op {
+        Op::Set(_, b) | Op::Not(_, b) | Op::Rmem(_, b) => vec![b],
+        Op::Eq(_, b, c)
+        | Op::Gt(_, b, c)
+        | Op::Add(_, b, c)
+        | Op::Mult(_, b, c)
+        | Op::Mod(_, b, c)
+        | Op::And(_, b, c)
+        | Op::Or(_, b, c) => vec![b, c],
+        Op::Wmem(a, b) | Op::Jt(a, b) | Op::Jf(a, b) => vec![a, b],
+        Op::Push(a) | Op::Jmp(a) | Op::Call(a) | Op::Out(a) => vec![a],
+        Op::Pop(_) | Op::In(_) | Op::Halt | Op::Ret | Op::Noop => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotations::Annotations;
+
+    const R0: Reg = Reg::REG0;
+    const R1: Reg = Reg::REG1;
+
+    fn analyze(ops: &[Op]) -> (Functions, Dataflow) {
+        let mut mem = Vec::new();
+        for op in ops {
+            op.encode(&mut mem);
+        }
+        let funcs = Functions::analyze(&mem, &Annotations::default());
+        let dataflow = Dataflow::analyze(&funcs);
+        (funcs, dataflow)
+    }
+
+    #[test]
+    fn propagates_constants_and_joins_paths() {
+        let (_, d) = analyze(&[
+            /* 0 */ Op::In(R1),
+            /* 2 */ Op::Set(R0, Val::Literal(2)),
+            /* 5 */ Op::Jt(Val::Reg(R1), Val::Literal(12)),
+            /* 8 */ Op::Set(R0, Val::Literal(3)),
+            /* 11 */ Op::Noop,
+            /* 12 */ Op::Mult(R0, Val::Reg(R0), Val::Literal(0x4000)),
+            /* 16 */ Op::Out(Val::Reg(R0)),
+            /* 18 */ Op::Halt,
+        ]);
+        let value = |addr: u16, r: Reg| d.states[&addr].values[r.index()].clone();
+        assert_eq!(value(5, R0), Value::known([2]));
+        assert_eq!(value(5, R1), Value::Unknown);
+        assert_eq!(value(12, R0), Value::known([2, 3]));
+        // 2 * 0x4000 and 3 * 0x4000, modulo 0x8000.
+        assert_eq!(value(16, R0), Value::known([0, 0x4000]));
+        assert_eq!(
+            d.states[&12].defs[R0.index()],
+            [Def::At(2), Def::At(8)].into()
+        );
+        assert_eq!(d.states[&2].defs[R0.index()], [Def::Entry].into());
+    }
+
+    #[test]
+    fn resolves_indirect_calls_through_arguments() {
+        let (funcs, d) = analyze(&[
+            /* 0 */ Op::Set(R1, Val::Literal(10)),
+            /* 3 */ Op::Call(Val::Literal(6)),
+            /* 5 */ Op::Halt,
+            /* 6 */ Op::Call(Val::Reg(R1)),
+            /* 8 */ Op::Ret,
+            /* 9 */ Op::Noop,
+            /* 10 */ Op::Ret,
+        ]);
+        let call = funcs.ops[&6];
+        assert_eq!(d.resolve(6, &call), Some([10].into()));
+        assert_eq!(d.known_reads(6, &call), [(R1, Value::known([10]))]);
+    }
+}
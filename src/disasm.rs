@@ -0,0 +1,207 @@
+use std::fmt::Write;
+
+use crate::{
+    annotations::{Annotations, DataRegion, DataType},
+    dataflow::Dataflow,
+    functions::Functions,
+    idiom::Idioms,
+    op::{Op, Val},
+};
+
+/// Column that trailing `;` comments are aligned to.
+const COMMENT_COL: usize = 40;
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Ops that start one of these are followed by its pseudo-op.
+    pub idioms: Idioms,
+    /// Show the statically known values of the registers each op reads.
+    pub values: bool,
+}
+
+/// Disassembles all of memory, one op per line, decorated with whatever `annotations` knows.
+/// Words that don't decode and aren't covered by a data region are skipped as binary data.
+pub fn decompile(
+    mem: &[u16],
+    annotations: &Annotations,
+    options: &Options,
+    out: &mut impl Write,
+) -> std::fmt::Result {
+    let dataflow = options
+        .values
+        .then(|| Dataflow::analyze(&Functions::analyze(mem, annotations)));
+    let mut addr = 0;
+    let mut in_data = false;
+    // End of the last idiom, so that ops inside one don't start another.
+    let mut idiom_end = 0;
+    while addr < mem.len() {
+        let a = addr as u16;
+        if let Some(f) = annotations.functions.get(&a) {
+            writeln!(out)?;
+            write!(out, "; function {}", f.name)?;
+            if let Some(sig) = &f.signature {
+                write!(out, " {sig}")?;
+            }
+            if !f.clobbers.is_empty() {
+                let regs: Vec<_> = f.clobbers.iter().map(|r| r.to_string()).collect();
+                write!(out, ", clobbers {}", regs.join(", "))?;
+            }
+            writeln!(out)?;
+        }
+        if let Some(data) = annotations.data.get(&a) {
+            if in_data {
+                writeln!(out, "\n")?;
+                in_data = false;
+            }
+            write_data(mem, data, out)?;
+            addr += data.len.max(1) as usize;
+            continue;
+        }
+        match Op::try_from(&mem[addr..]) {
+            Ok(op) => {
+                let mut out_line = String::new();
+                if in_data {
+                    // println!("\n\nops:");
+                    writeln!(out, "\n")?;
+                    in_data = false;
+                }
+                if let Some(label) = annotations.label(a) {
+                    writeln!(out, "{label}:")?;
+                }
+                write!(out_line, "/* 0x{addr:04x} */ {op}")?;
+                let idiom = (addr >= idiom_end)
+                    .then(|| options.idioms.recognize(mem, addr))
+                    .flatten()
+                    .map(|(text, end)| {
+                        idiom_end = end;
+                        text
+                    });
+                let comments = annotations.comments.get(&a);
+                let auto = comments
+                    .is_none()
+                    .then(|| auto_comment(&op, annotations))
+                    .flatten();
+                let values = dataflow
+                    .as_ref()
+                    .and_then(|d| values_comment(d, a, &op, annotations));
+                for text in idiom
+                    .iter()
+                    .chain(comments.into_iter().flatten())
+                    .chain(&auto)
+                    .chain(&values)
+                {
+                    for _ in out_line.len()..COMMENT_COL {
+                        write!(out_line, " ")?;
+                    }
+                    write!(out_line, "; {text}")?;
+                }
+                writeln!(out, "{out_line}")?;
+                addr += 1 + op.arg_count();
+                if let Some(f) = annotations.function_containing(a)
+                    && addr == f.end as usize
+                {
+                    writeln!(out, "; end {}", f.name)?;
+                }
+            }
+            Err(_err) => {
+                if !in_data {
+                    in_data = true;
+                    // println!("\ndata:");
+                    write!(out, "; binary data omitted")?;
+                }
+                // print!("{}", ((mem[offset] >> 8) as u8).escape_ascii());
+                // print!("{}", ((mem[offset] & 0xFF) as u8).escape_ascii());
+                addr += 1;
+                continue;
+            }
+        };
+    }
+    Ok(())
+}
+
+/// Names for the targets and memory cells an op refers to, when there is no hand-written
+/// comment for it.
+fn auto_comment(op: &Op, annotations: &Annotations) -> Option<String> {
+    match *op {
+        Op::Jmp(Val::Literal(to))
+        | Op::Jt(_, Val::Literal(to))
+        | Op::Jf(_, Val::Literal(to))
+        | Op::Call(Val::Literal(to)) => annotations.label(to).map(|l| format!("-> {l}")),
+        Op::Rmem(a, Val::Literal(at)) => {
+            let var = annotations.variables.get(&at)?;
+            Some(format!("{a} = {}", var.name))
+        }
+        Op::Wmem(Val::Literal(at), b) => {
+            let var = annotations.variables.get(&at)?;
+            match (b, &var.ty) {
+                (Val::Literal(x), Some(ty)) => match annotations.enum_value(at, x) {
+                    Some(name) => Some(format!("{} = {ty}::{name}", var.name)),
+                    None => Some(format!("{} = {b}", var.name)),
+                },
+                _ => Some(format!("{} = {b}", var.name)),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Known register values, and where an indirect call or jump goes if that's known.
+fn values_comment(
+    dataflow: &Dataflow,
+    addr: u16,
+    op: &Op,
+    annotations: &Annotations,
+) -> Option<String> {
+    let mut parts: Vec<_> = dataflow
+        .known_reads(addr, op)
+        .into_iter()
+        .map(|(r, v)| format!("{r} = {v}"))
+        .collect();
+    if let Some(targets) = dataflow.resolve(addr, op) {
+        let names: Vec<_> = targets
+            .iter()
+            .map(|&t| match annotations.label(t) {
+                Some(label) => label.to_owned(),
+                None => format!("0x{t:04x}"),
+            })
+            .collect();
+        parts.push(format!("-> {}", names.join(" | ")));
+    }
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+fn write_data(mem: &[u16], data: &DataRegion, out: &mut impl Write) -> std::fmt::Result {
+    let start = data.start as usize;
+    let end = (start + data.len as usize).min(mem.len());
+    let words = &mem[start.min(end)..end];
+    writeln!(out, "{}:", data.name)?;
+    match data.ty {
+        DataType::Words => {
+            for (i, chunk) in words.chunks(8).enumerate() {
+                let hex: Vec<_> = chunk.iter().map(|w| format!("0x{w:04x}")).collect();
+                writeln!(
+                    out,
+                    "/* 0x{:04x} */ .words {}",
+                    start + i * 8,
+                    hex.join(", ")
+                )?;
+            }
+        }
+        DataType::String | DataType::Strings => {
+            let mut i = 0;
+            while i < words.len() {
+                let len = words[i] as usize;
+                let text: String = words[i + 1..(i + 1 + len).min(words.len())]
+                    .iter()
+                    .map(|&w| char::from_u32(w as u32).unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect();
+                writeln!(out, "/* 0x{:04x} */ .string {text:?}", start + i)?;
+                i += 1 + len;
+                if data.ty == DataType::String {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
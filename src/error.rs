@@ -1,22 +1,71 @@
+use crate::script::ScriptError;
+
 #[derive(Debug)]
 pub enum Error {
     Halted,
-    PoppedEmptyStack,
+    /// `pop` at the given address found the stack empty.
+    PoppedEmptyStack(u16),
+    /// `mod` at the given address divided by zero.
+    DivideByZero(u16),
+    /// `out` at the given address was asked to print a value that isn't ascii.
+    NonAsciiOutput(u16, u16),
+    /// `in` at the given address read a byte that isn't ascii.
+    NonAsciiInput(u16, u8),
+    /// `in` at the given address found no more input to read.
+    InputExhausted(u16),
+    /// The op at the given address used an address outside the 15-bit address space.
+    AddressOutOfRange(u16, u16),
+    /// [`crate::machine::Machine::write_mem`] was given an address outside the 15-bit
+    /// address space or a value that isn't a 15-bit number: the address, then the value.
+    InvalidWrite(u16, u16),
+    /// The machine stopped at a breakpoint before executing the op at the given address.
+    Breakpoint(u16),
+    /// The machine executed its maximum number of steps.
+    StepLimit(u64),
+    /// A directive in the input script failed, e.g. an `@expect` that didn't match.
+    Script(ScriptError),
+    /// The instruction at the given address could not be decoded.
+    Decode(u16, Box<Error>),
     ParseReg,
     ParseRegFromU8,
     ParseVal(u16),
     ParseOp(u16),
+    /// The op's arguments run past the end of the input.
+    Truncated,
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::Halted => write!(f, "Halted"),
-            Error::PoppedEmptyStack => write!(f, "Popped from empty stack"),
+            Error::PoppedEmptyStack(addr) => {
+                write!(f, "Popped from empty stack at 0x{addr:04x}")
+            }
+            Error::DivideByZero(addr) => write!(f, "Division by zero at 0x{addr:04x}"),
+            Error::NonAsciiOutput(addr, val) => {
+                write!(f, "Non-ascii output {val} at 0x{addr:04x}")
+            }
+            Error::NonAsciiInput(addr, byte) => {
+                write!(f, "Non-ascii input {byte} at 0x{addr:04x}")
+            }
+            Error::InputExhausted(addr) => write!(f, "Input exhausted at 0x{addr:04x}"),
+            Error::InvalidWrite(addr, val) => {
+                write!(f, "Can't write {val} to address {addr}")
+            }
+            Error::Breakpoint(addr) => write!(f, "Breakpoint at 0x{addr:04x}"),
+            Error::StepLimit(steps) => write!(f, "Stopped after {steps} steps"),
+            Error::Script(err) => write!(f, "Script failed at {err}"),
+            Error::Decode(addr, err) => write!(f, "Failed to decode op at 0x{addr:04x}: {err}"),
             Error::ParseReg => write!(f, "Failed to parse register"),
             Error::ParseRegFromU8 => write!(f, "Failed to parse register from u8"),
             Error::ParseVal(input) => write!(f, "Failed to parse value from {input}"),
             Error::ParseOp(input) => write!(f, "Failed to parse op from {input}"),
+            Error::Truncated => write!(f, "Op is truncated"),
+            Error::AddressOutOfRange(addr, target) => {
+                write!(f, "Address {target} out of range at 0x{addr:04x}")
+            }
         }
     }
 }
+
+impl std::error::Error for Error {}
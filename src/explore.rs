@@ -0,0 +1,334 @@
+//! Mapping the game world by trying every exit of every room, going back to a snapshot of the
+//! room before each one.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::machine::{Buffer, Machine};
+
+/// Most ops a single command may take before it's given up on. Moving between rooms takes a
+/// few thousand.
+const STEP_BUDGET: u64 = 1_000_000;
+
+/// A room as the game describes it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Room {
+    pub name: String,
+    pub description: String,
+    pub items: Vec<String>,
+    pub exits: Vec<Exit>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exit {
+    pub name: String,
+    /// Index of the room it leads to in [`Map::rooms`], or `None` if taking it didn't end up
+    /// in a room, e.g. because the game ended.
+    pub to: Option<usize>,
+}
+
+/// Rooms in the order they were found, starting with the one exploring started in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Map {
+    pub rooms: Vec<Room>,
+}
+
+impl Map {
+    pub fn to_json(&self) -> String {
+        let mut rooms = Vec::new();
+        for (id, room) in self.rooms.iter().enumerate() {
+            let items: Vec<_> = room.items.iter().map(|i| quote(i)).collect();
+            let exits: Vec<_> = room
+                .exits
+                .iter()
+                .map(|e| {
+                    let to = e.to.map_or("null".to_owned(), |to| to.to_string());
+                    format!("{}: {to}", quote(&e.name))
+                })
+                .collect();
+            let mut out = String::from("    {\n");
+            out += &format!("      \"id\": {id},\n");
+            out += &format!("      \"name\": {},\n", quote(&room.name));
+            out += &format!("      \"description\": {},\n", quote(&room.description));
+            out += &format!("      \"items\": [{}],\n", items.join(", "));
+            out += &format!("      \"exits\": {{{}}}\n", exits.join(", "));
+            out += "    }";
+            rooms.push(out);
+        }
+        format!("{{\n  \"rooms\": [\n{}\n  ]\n}}\n", rooms.join(",\n"))
+    }
+
+    /// Ids of the rooms called `name`.
+    pub fn find(&self, name: &str) -> Vec<usize> {
+        (0..self.rooms.len())
+            .filter(|&id| self.rooms[id].name == name)
+            .collect()
+    }
+
+    /// The fewest exits to take to get from room `from` to room `to`, each with the room it
+    /// leads to, or `None` if `to` can't be reached.
+    pub fn route(&self, from: usize, to: usize) -> Option<Vec<(&str, usize)>> {
+        let mut prev = HashMap::from([(from, None)]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(id) = queue.pop_front() {
+            if id == to {
+                let mut route = Vec::new();
+                let mut at = to;
+                while let Some(Some((exit, before))) = prev.get(&at) {
+                    route.push((*exit, at));
+                    at = *before;
+                }
+                route.reverse();
+                return Some(route);
+            }
+            for exit in &self.rooms[id].exits {
+                if let Some(next) = exit.to
+                    && !prev.contains_key(&next)
+                {
+                    prev.insert(next, Some((exit.name.as_str(), id)));
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// `route` from room `from` as input for [`crate::machine::Machine::set_script`], with a
+    /// comment naming each room on the way.
+    pub fn script(&self, from: usize, route: &[(&str, usize)]) -> String {
+        let to = route.last().map_or(from, |(_, to)| *to);
+        let mut out = format!(
+            "// from {} ({from}) to {} ({to})\n",
+            self.rooms[from].name, self.rooms[to].name
+        );
+        for (exit, room) in route {
+            out += &format!("go {exit}\n// {} ({room})\n", self.rooms[*room].name);
+        }
+        out
+    }
+
+    /// A Graphviz digraph with a node per room, labelled with its name and items, and an edge
+    /// per exit. Exits that don't lead to a room go to a shared `nowhere` node.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph map {\n");
+        let mut nowhere = false;
+        for (id, room) in self.rooms.iter().enumerate() {
+            let mut label = room.name.clone();
+            if !room.items.is_empty() {
+                label += &format!("\n({})", room.items.join(", "));
+            }
+            out += &format!("  r{id} [label={}];\n", quote(&label));
+            for exit in &room.exits {
+                let to = exit.to.map_or("nowhere".to_owned(), |to| format!("r{to}"));
+                nowhere |= exit.to.is_none();
+                out += &format!("  r{id} -> {to} [label={}];\n", quote(&exit.name));
+            }
+        }
+        if nowhere {
+            out += "  nowhere [label=\"(no room)\", shape=plaintext];\n";
+        }
+        out += "}\n";
+        out
+    }
+}
+
+/// A string literal that both JSON and DOT accept.
+fn quote(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The last room described in `output`, with its exits not leading anywhere yet.
+pub fn parse_room(output: &str) -> Option<Room> {
+    let lines: Vec<&str> = output.lines().collect();
+    let start = lines
+        .iter()
+        .rposition(|l| l.starts_with("== ") && l.ends_with(" =="))?;
+    let name = lines[start];
+    let mut description = Vec::new();
+    let (mut items, mut exits) = (Vec::new(), Vec::new());
+    let mut list: Option<&mut Vec<String>> = None;
+    for line in &lines[start + 1..] {
+        if line.starts_with("Things of interest here:") {
+            list = Some(&mut items);
+        } else if *line == "There is 1 exit:"
+            || line.starts_with("There are ") && line.ends_with(" exits:")
+        {
+            list = Some(&mut exits);
+        } else if line.starts_with("What do you do?") {
+            break;
+        } else if let Some(list) = &mut list {
+            if let Some(entry) = line.strip_prefix("- ") {
+                list.push(entry.to_owned());
+            }
+        } else {
+            description.push(*line);
+        }
+    }
+    Some(Room {
+        name: name[3..name.len() - 3].to_owned(),
+        description: description.join("\n").trim().to_owned(),
+        items,
+        exits: exits
+            .into_iter()
+            .map(|name| Exit { name, to: None })
+            .collect(),
+    })
+}
+
+/// Sends `command`, returning the room the game describes in response, if any.
+fn command(machine: &mut Machine<Buffer>, command: &str) -> Option<Room> {
+    machine.set_max_steps(Some(machine.steps() + STEP_BUDGET));
+    parse_room(&machine.command(command).ok()?)
+}
+
+/// Explores breadth-first from the room the machine is in, which must be waiting for input.
+/// The machine is left in whatever state the last exit tried put it in.
+///
+/// Rooms are told apart by the word at `current_room` if the ROM's
+/// [`crate::rom::Profile`] knows it, else by their text, which merges look-alike rooms such
+/// as the maze's.
+pub fn explore(machine: &mut Machine<Buffer>, current_room: Option<u16>) -> Result<Map, String> {
+    let key = |machine: &Machine<Buffer>, room: &Room| match current_room {
+        Some(addr) => format!("{:04x}", machine.mem()[addr as usize]),
+        None => format!("{room:?}"),
+    };
+    let room = command(machine, "look").ok_or("`look` didn't describe a room")?;
+    let mut seen = HashMap::from([(key(machine, &room), 0)]);
+    let mut map = Map { rooms: vec![room] };
+    let mut queue = VecDeque::from([(0, machine.snapshot())]);
+    while let Some((from, snapshot)) = queue.pop_front() {
+        for i in 0..map.rooms[from].exits.len() {
+            machine.restore(&snapshot);
+            let go = format!("go {}", map.rooms[from].exits[i].name);
+            let Some(room) = command(machine, &go) else {
+                continue;
+            };
+            let next = map.rooms.len();
+            let to = *seen.entry(key(machine, &room)).or_insert(next);
+            if to == next {
+                map.rooms.push(room);
+                queue.push_back((to, machine.snapshot()));
+            }
+            map.rooms[from].exits[i].to = Some(to);
+        }
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{error::Error, machine::load_rom, rom};
+
+    #[test]
+    fn parses_room() {
+        let output = "\
+== Nowhere ==
+Old text.
+
+What do you do?
+
+As you begin to leave, you feel the urge for adventure pulling you back...
+== Foothills ==
+You find yourself standing at the base of an enormous mountain.
+A sign reads \"Keep out!\"
+
+The floor is dusty.
+
+Things of interest here:
+- tablet
+
+There are 2 exits:
+- doorway
+- south
+
+What do you do?
+";
+        let room = parse_room(output).unwrap();
+        assert_eq!(room.name, "Foothills");
+        assert_eq!(
+            room.description,
+            "You find yourself standing at the base of an enormous mountain.\nA sign reads \"Keep out!\"\n\nThe floor is dusty."
+        );
+        assert_eq!(room.items, ["tablet"]);
+        let exits: Vec<_> = room.exits.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(exits, ["doorway", "south"]);
+        assert_eq!(parse_room("I don't understand.\n"), None);
+    }
+
+    #[test]
+    fn routes_take_fewest_exits() {
+        let room = |name: &str, exits: &[(&str, usize)]| Room {
+            name: name.to_owned(),
+            exits: exits
+                .iter()
+                .map(|&(name, to)| Exit {
+                    name: name.to_owned(),
+                    to: Some(to),
+                })
+                .collect(),
+            ..Room::default()
+        };
+        let map = Map {
+            rooms: vec![
+                room("a", &[("north", 1), ("east", 2)]),
+                room("b", &[("north", 3), ("south", 0)]),
+                room("c", &[("north", 1), ("east", 3)]),
+                room("d", &[]),
+                room("e", &[("west", 0)]),
+            ],
+        };
+        let route = map.route(0, 3).unwrap();
+        assert_eq!(route, [("north", 1), ("north", 3)]);
+        assert_eq!(
+            map.script(0, &route),
+            "// from a (0) to d (3)\ngo north\n// b (1)\ngo north\n// d (3)\n"
+        );
+        assert_eq!(map.route(2, 2), Some(vec![]));
+        assert_eq!(map.route(0, 4), None);
+        assert_eq!(map.find("c"), [2]);
+    }
+
+    #[test]
+    fn explores_from_the_start() {
+        let mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let profile = rom::detect(&mem);
+        let mut machine = Machine::with_console(mem, Buffer::default());
+        assert!(matches!(machine.run(), Err(Error::InputExhausted(_))));
+        let map = explore(&mut machine, profile.current_room).unwrap();
+
+        let start = &map.rooms[0];
+        assert_eq!(start.name, "Foothills");
+        assert_eq!(start.items, ["tablet"]);
+        let named = |name| map.rooms.iter().filter(|r| r.name == name).count();
+        assert!(named("Dark cave") > 0);
+        // The maze's rooms all read the same but are told apart.
+        assert!(named("Twisty passages") > 1);
+        // Without a lit lantern, the grue gets you.
+        let lost = map
+            .rooms
+            .iter()
+            .find(|r| r.name == "Panicked and lost")
+            .unwrap();
+        assert!(lost.exits.iter().all(|e| e.to.is_none()));
+        assert!(
+            map.rooms
+                .iter()
+                .any(|r| r.items.contains(&"empty lantern".to_owned()))
+        );
+        assert!(map.to_dot().contains("r0 -> r"));
+        assert!(map.to_json().contains("\"name\": \"Foothills\""));
+    }
+}
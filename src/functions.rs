@@ -0,0 +1,439 @@
+//! Splitting code into functions and working out which registers they take and return.
+//!
+//! There is no calling convention in the arch-spec, so arguments are the registers a function
+//! reads before writing, and results are the registers it changes that some caller reads after
+//! the call. Registers pushed by the leading pushes at entry are taken to be saved and restored.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{
+    annotations::{self, Annotations},
+    cfg,
+    op::{Op, Reg, Val},
+};
+
+/// A set of registers, bit `n` for `rn`.
+pub type Regs = u8;
+
+pub fn reg_bit(r: Reg) -> Regs {
+    1 << r.index()
+}
+
+fn val_bit(v: Val) -> Regs {
+    match v {
+        Val::Reg(r) => reg_bit(r),
+        Val::Literal(_) => 0,
+    }
+}
+
+/// The registers in `regs`, lowest first.
+pub fn regs(regs: Regs) -> impl Iterator<Item = Reg> {
+    (0..8u8)
+        .filter(move |n| regs & (1 << n) != 0)
+        .map(|n| Reg::try_from(n).unwrap())
+}
+
+/// Address of the op following the one at `addr`.
+pub fn next_addr(addr: u16, op: &Op) -> u16 {
+    addr.wrapping_add(1 + op.arg_count() as u16)
+}
+
+/// Where control can go after the op at `addr`, not counting calls.
+pub fn successors(addr: u16, op: &Op) -> Vec<u16> {
+    let next = next_addr(addr, op);
+    match *op {
+        Op::Halt | Op::Ret | Op::Jmp(Val::Reg(_)) => vec![],
+        Op::Jmp(Val::Literal(to)) => vec![to],
+        Op::Jt(_, Val::Literal(to)) | Op::Jf(_, Val::Literal(to)) => vec![next, to],
+        _ => vec![next],
+    }
+}
+
+/// Registers read and written by `op` itself. Calls and returns get their effects from the
+/// functions involved. Pushes aren't counted as reads, since they are almost always saves.
+fn uses_defs(op: &Op) -> (Regs, Regs) {
+    match *op {
+        Op::Set(a, b) | Op::Not(a, b) | Op::Rmem(a, b) => (val_bit(b), reg_bit(a)),
+        Op::Pop(a) | Op::In(a) => (0, reg_bit(a)),
+        Op::Eq(a, b, c)
+        | Op::Gt(a, b, c)
+        | Op::Add(a, b, c)
+        | Op::Mult(a, b, c)
+        | Op::Mod(a, b, c)
+        | Op::And(a, b, c)
+        | Op::Or(a, b, c) => (val_bit(b) | val_bit(c), reg_bit(a)),
+        Op::Wmem(a, b) | Op::Jt(a, b) | Op::Jf(a, b) => (val_bit(a) | val_bit(b), 0),
+        Op::Jmp(a) | Op::Call(a) | Op::Out(a) => (val_bit(a), 0),
+        Op::Push(_) | Op::Halt | Op::Ret | Op::Noop => (0, 0),
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Function {
+    pub entry: u16,
+    pub name: String,
+    /// Addresses of the function's ops, in order.
+    pub body: Vec<u16>,
+    /// Registers pushed on entry, and so restored before returning.
+    pub saved: Regs,
+    pub args: Regs,
+    pub returns: Regs,
+    /// Registers changed by the function or its callees, other than the saved ones.
+    pub modified: Regs,
+    /// Registers live after each op.
+    pub live_out: HashMap<u16, Regs>,
+    pub stack: Stack,
+}
+
+impl Function {
+    /// The inferred signature, e.g. `(r0, r1) -> r0`.
+    pub fn signature(&self) -> String {
+        let list = |r: Regs| {
+            regs(r)
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self.returns.count_ones() {
+            0 => format!("({})", list(self.args)),
+            1 => format!("({}) -> {}", list(self.args), list(self.returns)),
+            _ => format!("({}) -> ({})", list(self.args), list(self.returns)),
+        }
+    }
+
+    /// Registers changed but not returned.
+    pub fn clobbers(&self) -> Regs {
+        self.modified & !self.returns
+    }
+}
+
+/// How a function uses the stack, in words relative to its entry.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stack {
+    /// Net pushes minus pops of each basic block, by its first address.
+    pub blocks: BTreeMap<u16, i32>,
+    pub max_depth: i32,
+    /// The depth at every `ret`, if they agree. Balanced functions return at 0.
+    pub effect: Option<i32>,
+    pub issues: Vec<StackIssue>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StackIssue {
+    /// Paths reach the op at `at` with different depths.
+    Mismatch { at: u16, depths: (i32, i32) },
+    /// A `ret` with words still pushed, or with more popped than pushed.
+    Ret { at: u16, depth: i32 },
+    /// A `pop` with nothing pushed, which takes the return address.
+    Underflow { at: u16 },
+}
+
+impl std::fmt::Display for StackIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackIssue::Mismatch { at, depths: (a, b) } => {
+                write!(f, "0x{at:04x}: reached with stack depths {a} and {b}")
+            }
+            StackIssue::Ret { at, depth } => write!(f, "0x{at:04x}: ret at stack depth {depth}"),
+            StackIssue::Underflow { at } => write!(f, "0x{at:04x}: pop with nothing pushed"),
+        }
+    }
+}
+
+pub struct Functions {
+    pub ops: BTreeMap<u16, Op>,
+    pub functions: BTreeMap<u16, Function>,
+}
+
+impl Functions {
+    /// Finds functions at address 0, annotated starts and literal call targets, and infers
+    /// their arguments and results.
+    pub fn analyze(mem: &[u16], annotations: &Annotations) -> Functions {
+        let ops = cfg::sweep(mem, annotations);
+        let (calls, _) = cfg::targets(&ops);
+        let entries: BTreeSet<u16> = calls
+            .into_iter()
+            .chain(annotations.functions.keys().copied())
+            .chain([0])
+            .filter(|a| ops.contains_key(a))
+            .collect();
+        let functions = entries
+            .into_iter()
+            .map(|entry| {
+                let name = match annotations.label(entry) {
+                    Some(label) => label.to_owned(),
+                    None if entry == 0 => "main".to_owned(),
+                    None => format!("sub_{entry:04x}"),
+                };
+                let f = Function {
+                    entry,
+                    name,
+                    body: body(&ops, entry),
+                    saved: saved(&ops, entry),
+                    ..Default::default()
+                };
+                (entry, f)
+            })
+            .collect();
+        let mut this = Functions { ops, functions };
+        this.infer();
+        for f in this.functions.values_mut() {
+            f.stack = stack(&this.ops, &f.body, f.entry);
+        }
+        this
+    }
+
+    /// `f` as a `[function]` annotation, ending after its last op.
+    pub fn annotation(&self, f: &Function) -> annotations::Function {
+        let end = f
+            .body
+            .iter()
+            .map(|a| a + 1 + self.ops[a].arg_count() as u16)
+            .max()
+            .unwrap_or(f.entry + 1);
+        annotations::Function {
+            name: f.name.clone(),
+            start: f.entry,
+            end,
+            signature: Some(f.signature()),
+            clobbers: regs(f.clobbers()).collect(),
+        }
+    }
+
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.functions.get(&addr).map(|f| f.name.as_str())
+    }
+
+    /// Registers read and written by the op at `addr`, including a callee's arguments and
+    /// changes, and the function's results at a return.
+    pub fn effects(&self, f: &Function, op: &Op) -> (Regs, Regs) {
+        let (uses, defs) = uses_defs(op);
+        match *op {
+            Op::Call(Val::Literal(to)) => match self.functions.get(&to) {
+                Some(callee) => (uses | callee.args, defs | callee.modified),
+                None => (uses, defs),
+            },
+            Op::Ret => (uses | f.returns, defs),
+            _ => (uses, defs),
+        }
+    }
+
+    /// Iterates liveness, arguments, changes and results across all functions until nothing
+    /// changes. Bounded, since recursion can keep it from settling.
+    fn infer(&mut self) {
+        for _ in 0..32 {
+            let mut changed = false;
+            let entries: Vec<u16> = self.functions.keys().copied().collect();
+            for &entry in &entries {
+                let f = &self.functions[&entry];
+                let live_out = self.liveness(f);
+                let mut modified = 0;
+                for addr in &f.body {
+                    let op = &self.ops[addr];
+                    if !matches!(op, Op::Pop(_)) {
+                        modified |= self.effects(f, op).1;
+                    }
+                }
+                let modified = modified & !f.saved;
+                let (uses, defs) = self.effects(f, &self.ops[&entry]);
+                let args = uses | (live_out[&entry] & !defs);
+                let f = self.functions.get_mut(&entry).unwrap();
+                changed |= f.args != args || f.modified != modified || f.live_out != live_out;
+                f.args = args;
+                f.modified = modified;
+                f.live_out = live_out;
+            }
+            let mut returns: HashMap<u16, Regs> = HashMap::new();
+            for f in self.functions.values() {
+                for addr in &f.body {
+                    if let Op::Call(Val::Literal(to)) = self.ops[addr]
+                        && let Some(callee) = self.functions.get(&to)
+                    {
+                        *returns.entry(to).or_default() |= f.live_out[addr] & callee.modified;
+                    }
+                }
+            }
+            for (entry, f) in &mut self.functions {
+                let r = returns.get(entry).copied().unwrap_or(0);
+                changed |= f.returns != r;
+                f.returns = r;
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    fn liveness(&self, f: &Function) -> HashMap<u16, Regs> {
+        let mut live_in: HashMap<u16, Regs> = HashMap::new();
+        let mut live_out: HashMap<u16, Regs> = f.body.iter().map(|&a| (a, 0)).collect();
+        loop {
+            let mut changed = false;
+            for addr in f.body.iter().rev() {
+                let op = &self.ops[addr];
+                let out = successors(*addr, op)
+                    .iter()
+                    .map(|s| live_in.get(s).copied().unwrap_or(0))
+                    .fold(0, |a, b| a | b);
+                let (uses, defs) = self.effects(f, op);
+                let inn = uses | (out & !defs);
+                changed |= live_out[addr] != out || live_in.get(addr) != Some(&inn);
+                live_out.insert(*addr, out);
+                live_in.insert(*addr, inn);
+            }
+            if !changed {
+                return live_out;
+            }
+        }
+    }
+}
+
+/// Every op reachable from `entry` without following calls.
+fn body(ops: &BTreeMap<u16, Op>, entry: u16) -> Vec<u16> {
+    let mut seen = BTreeSet::new();
+    let mut todo = vec![entry];
+    while let Some(addr) = todo.pop() {
+        let Some(op) = ops.get(&addr) else { continue };
+        if seen.insert(addr) {
+            todo.extend(successors(addr, op));
+        }
+    }
+    seen.into_iter().collect()
+}
+
+/// Walks `body` from `entry` tracking the stack depth. Calls are taken to leave the stack as
+/// they found it.
+fn stack(ops: &BTreeMap<u16, Op>, body: &[u16], entry: u16) -> Stack {
+    let delta = |op: &Op| match op {
+        Op::Push(_) => 1,
+        Op::Pop(_) => -1,
+        _ => 0,
+    };
+    let mut result = Stack::default();
+    let mut depths: HashMap<u16, i32> = HashMap::from([(entry, 0)]);
+    let mut rets = BTreeSet::new();
+    let mut todo = vec![entry];
+    while let Some(addr) = todo.pop() {
+        let op = &ops[&addr];
+        let depth = depths[&addr];
+        let after = depth + delta(op);
+        if after < 0 && matches!(op, Op::Pop(_)) {
+            result.issues.push(StackIssue::Underflow { at: addr });
+        }
+        result.max_depth = result.max_depth.max(after);
+        if let Op::Ret = op {
+            rets.insert(depth);
+            if depth != 0 {
+                result.issues.push(StackIssue::Ret { at: addr, depth });
+            }
+        }
+        for next in successors(addr, op) {
+            if !ops.contains_key(&next) {
+                continue;
+            }
+            match depths.get(&next) {
+                None => {
+                    depths.insert(next, after);
+                    todo.push(next);
+                }
+                Some(&d) if d != after => {
+                    let issue = StackIssue::Mismatch {
+                        at: next,
+                        depths: (d.min(after), d.max(after)),
+                    };
+                    if !result.issues.contains(&issue) {
+                        result.issues.push(issue);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    result.issues.sort_by_key(|i| match i {
+        StackIssue::Mismatch { at, .. }
+        | StackIssue::Ret { at, .. }
+        | StackIssue::Underflow { at } => *at,
+    });
+    result.effect = (rets.len() == 1).then(|| *rets.first().unwrap());
+
+    // Blocks start at the entry, at jump targets and after jumps.
+    let mut leaders = BTreeSet::from([entry]);
+    for addr in body {
+        let op = &ops[addr];
+        if let Op::Jmp(_) | Op::Jt(..) | Op::Jf(..) = op {
+            leaders.extend(successors(*addr, op));
+        }
+    }
+    let mut block = entry;
+    for addr in body {
+        if leaders.contains(addr) {
+            block = *addr;
+        }
+        *result.blocks.entry(block).or_default() += delta(&ops[addr]);
+    }
+    result
+}
+
+fn saved(ops: &BTreeMap<u16, Op>, entry: u16) -> Regs {
+    let mut saved = 0;
+    let mut addr = entry;
+    while let Some(op @ Op::Push(Val::Reg(r))) = ops.get(&addr) {
+        saved |= reg_bit(*r);
+        addr = next_addr(addr, op);
+    }
+    saved
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::machine::load_rom;
+
+    #[test]
+    fn infers_known_signatures() {
+        let mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let funcs = Functions::analyze(&mem, &Annotations::default());
+        let sig = |addr| {
+            let f = &funcs.functions[&addr];
+            (f.args, f.returns, f.modified)
+        };
+        // recursive_func(r0, r1) -> r0, and it needs r7.
+        assert_eq!(sig(0x17a1), (0b1000_0011, 0b1, 0b11));
+        // print_string(r0) restores the r1 it uses for the callback.
+        assert_eq!(sig(0x0604), (0b1, 0, 0));
+        let stack = &funcs.functions[&0x17a1].stack;
+        assert_eq!((stack.max_depth, stack.effect), (1, Some(0)));
+        assert!(stack.issues.is_empty());
+    }
+
+    #[test]
+    fn flags_unbalanced_stacks() {
+        let mut mem = Vec::new();
+        for op in [
+            /* 0 */ Op::Call(Val::Literal(3)),
+            /* 2 */ Op::Halt,
+            /* 3 */ Op::Jt(Val::Reg(Reg::REG0), Val::Literal(8)),
+            /* 6 */ Op::Push(Val::Reg(Reg::REG0)),
+            /* 8 */ Op::Pop(Reg::REG1),
+            /* 10 */ Op::Ret,
+        ] {
+            op.encode(&mut mem);
+        }
+        let funcs = Functions::analyze(&mem, &Annotations::default());
+        let stack = &funcs.functions[&3].stack;
+        assert_eq!(
+            stack.issues,
+            [
+                StackIssue::Underflow { at: 8 },
+                StackIssue::Mismatch {
+                    at: 8,
+                    depths: (0, 1)
+                },
+                StackIssue::Ret { at: 10, depth: -1 },
+            ]
+        );
+        assert_eq!(stack.blocks, BTreeMap::from([(3, 0), (6, 1), (8, -1)]));
+    }
+}
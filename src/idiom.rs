@@ -0,0 +1,195 @@
+//! Recurring instruction sequences and the pseudo-ops `decompile` prints for them.
+
+use std::collections::BTreeSet;
+
+use crate::op::{Op, Val};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Idiom {
+    /// `add a, b, 0x7fff` is `sub a, b, 1`: adding a literal just short of 0x8000 subtracts.
+    /// Larger constants, further from it, stay additions.
+    Sub,
+    /// `not a, b; add a, a, 1` is `neg a, b`.
+    Neg,
+    /// Runs of `push` or `pop` are register saves and restores.
+    Save,
+    /// `call` through a register.
+    IndirectCall,
+}
+
+/// The smallest literal [`Idiom::Sub`] treats as a negative number.
+const SUB_FROM: u16 = 0x7f00;
+
+impl Idiom {
+    pub const ALL: [Idiom; 4] = [Idiom::Sub, Idiom::Neg, Idiom::Save, Idiom::IndirectCall];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Idiom::Sub => "sub",
+            Idiom::Neg => "neg",
+            Idiom::Save => "save",
+            Idiom::IndirectCall => "icall",
+        }
+    }
+}
+
+impl std::str::FromStr for Idiom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Idiom::ALL
+            .into_iter()
+            .find(|i| i.name() == s)
+            .ok_or_else(|| format!("unknown idiom {s:?}"))
+    }
+}
+
+/// The idioms to recognize. All of them by default.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Idioms(BTreeSet<Idiom>);
+
+impl Default for Idioms {
+    fn default() -> Self {
+        Idioms(Idiom::ALL.into())
+    }
+}
+
+impl Idioms {
+    pub fn none() -> Self {
+        Idioms(BTreeSet::new())
+    }
+
+    pub fn contains(&self, idiom: Idiom) -> bool {
+        self.0.contains(&idiom)
+    }
+
+    /// Parses a comma-separated list of idiom names, or `all` or `none`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "all" => Ok(Idioms::default()),
+            "none" => Ok(Idioms::none()),
+            _ => s.split(',').map(|n| n.trim().parse()).collect(),
+        }
+    }
+
+    /// The pseudo-op for the idiom starting at `addr`, and the address just past it.
+    pub fn recognize(&self, mem: &[u16], addr: usize) -> Option<(String, usize)> {
+        let decode = |at: usize| {
+            let op = Op::try_from(mem.get(at..)?).ok()?;
+            Some((op, at + 1 + op.arg_count()))
+        };
+        let (op, next) = decode(addr)?;
+        match op {
+            Op::Add(a, b, Val::Literal(c)) if c >= SUB_FROM && self.contains(Idiom::Sub) => {
+                Some((format!("sub {a}, {b}, {}", 0x8000 - c), next))
+            }
+            Op::Not(a, b) if self.contains(Idiom::Neg) => match decode(next)? {
+                (Op::Add(x, Val::Reg(y), Val::Literal(1)), end) if x == a && y == a => {
+                    Some((format!("neg {a}, {b}"), end))
+                }
+                _ => None,
+            },
+            Op::Push(Val::Reg(_)) | Op::Pop(_) if self.contains(Idiom::Save) => {
+                let pushing = matches!(op, Op::Push(_));
+                let mut regs = Vec::new();
+                let mut end = addr;
+                while let Some((op, after)) = decode(end) {
+                    match op {
+                        Op::Push(Val::Reg(r)) if pushing => regs.push(r),
+                        Op::Pop(r) if !pushing => regs.push(r),
+                        _ => break,
+                    }
+                    end = after;
+                }
+                (regs.len() > 1).then(|| {
+                    let regs: Vec<_> = regs.iter().map(|r| r.to_string()).collect();
+                    let name = if pushing { "save" } else { "restore" };
+                    (format!("{name} {}", regs.join(", ")), end)
+                })
+            }
+            Op::Call(Val::Reg(r)) if self.contains(Idiom::IndirectCall) => {
+                Some((format!("icall {r}"), next))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FromIterator<Idiom> for Idioms {
+    fn from_iter<T: IntoIterator<Item = Idiom>>(iter: T) -> Self {
+        Idioms(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op::Reg;
+
+    const R0: Reg = Reg::REG0;
+    const R1: Reg = Reg::REG1;
+
+    #[test]
+    fn recognizes_idioms() {
+        let cases: &[(&[Op], Option<&str>)] = &[
+            (
+                &[Op::Add(R0, Val::Reg(R0), Val::Literal(0x7fff))],
+                Some("sub r0, r0, 1"),
+            ),
+            (
+                &[Op::Add(R0, Val::Reg(R1), Val::Literal(0x7ffd))],
+                Some("sub r0, r1, 3"),
+            ),
+            (&[Op::Add(R0, Val::Reg(R0), Val::Literal(3))], None),
+            (&[Op::Add(R0, Val::Reg(R0), Val::Literal(0x6000))], None),
+            (
+                &[Op::Add(R0, Val::Reg(R0), Val::Literal(0x7f00))],
+                Some("sub r0, r0, 256"),
+            ),
+            (
+                &[
+                    Op::Not(R0, Val::Reg(R1)),
+                    Op::Add(R0, Val::Reg(R0), Val::Literal(1)),
+                ],
+                Some("neg r0, r1"),
+            ),
+            (
+                &[
+                    Op::Not(R0, Val::Reg(R1)),
+                    Op::Add(R1, Val::Reg(R0), Val::Literal(1)),
+                ],
+                None,
+            ),
+            (
+                &[Op::Push(Val::Reg(R0)), Op::Push(Val::Reg(R1)), Op::Pop(R1)],
+                Some("save r0, r1"),
+            ),
+            (&[Op::Pop(R1), Op::Pop(R0), Op::Ret], Some("restore r1, r0")),
+            (&[Op::Push(Val::Reg(R0)), Op::Ret], None),
+            (&[Op::Call(Val::Reg(R1))], Some("icall r1")),
+        ];
+        for (ops, want) in cases {
+            let mut mem = Vec::new();
+            for op in *ops {
+                op.encode(&mut mem);
+            }
+            let got = Idioms::default().recognize(&mem, 0).map(|(s, _)| s);
+            assert_eq!(got.as_deref(), *want, "{ops:?}");
+        }
+    }
+
+    #[test]
+    fn idioms_can_be_turned_off() {
+        let mut mem = Vec::new();
+        Op::Add(R0, Val::Reg(R0), Val::Literal(0x7fff)).encode(&mut mem);
+        Op::Call(Val::Reg(R1)).encode(&mut mem);
+        let idioms = Idioms::parse("icall").unwrap();
+        assert_eq!(idioms.recognize(&mem, 0), None);
+        assert_eq!(idioms.recognize(&mem, 4), Some(("icall r1".to_owned(), 6)));
+        assert_eq!(Idioms::parse("none").unwrap().recognize(&mem, 4), None);
+        assert_eq!(
+            Idioms::parse("sub,bogus"),
+            Err("unknown idiom \"bogus\"".to_owned())
+        );
+    }
+}
@@ -0,0 +1,21 @@
+pub mod ackermann;
+pub mod annotations;
+pub mod cfg;
+pub mod cheat;
+pub mod codes;
+pub mod coins;
+pub mod dataflow;
+pub mod disasm;
+pub mod error;
+pub mod explore;
+pub mod functions;
+pub mod idiom;
+pub mod machine;
+pub mod op;
+pub mod orb;
+pub mod pseudo;
+pub mod rom;
+pub mod script;
+pub mod signature;
+pub mod state;
+pub mod xref;
@@ -1,53 +1,230 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap, VecDeque},
     fs::{File, OpenOptions},
     io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
 use crate::{
+    annotations::{Annotations, AnnotationsFile},
+    cheat,
     error::Error,
     op::{Op, Reg, Val},
+    script::{Item, Line, Script},
+    state::{Layout, State},
 };
 
 pub const MAX_U15: u16 = (1 << 15) - 1;
 pub const MOD: u16 = 1 << 15;
-pub const R7_VAL: u16 = 25734;
 
+/// Input lines starting with this are meta-commands for the machine rather than input for the
+/// program, unless [`Machine::set_meta_prefix`] says otherwise.
+pub const META_PREFIX: &str = "!";
+
+/// Reads a ROM image: 16-bit little-endian words, loaded from address 0.
+pub fn load_rom(path: &Path) -> std::io::Result<Vec<u16>> {
+    let rom_data = std::fs::read(path)?;
+    let (chunks, _) = rom_data.as_chunks::<2>();
+    Ok(chunks.iter().cloned().map(u16::from_le_bytes).collect())
+}
+
+/// How the machine treats states that the arch-spec calls errors or leaves undefined.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Every spec violation stops the machine with an [`Error`] carrying the faulting address.
+    Strict,
+    /// Keep running with a fallback for each spec violation:
+    ///
+    /// - `pop` on an empty stack halts, the same as `ret` on an empty stack.
+    /// - `mod` by zero stores 0.
+    /// - `out` of a value above 127 prints its low byte.
+    /// - `in` of a byte above 127 stores it as-is.
+    /// - `rmem` and `wmem` of an address above 32767 wrap it to 15 bits.
+    #[default]
+    Lenient,
+}
+
+/// Where `in` reads from and `out` writes to.
+pub trait Console {
+    /// Reads the next input byte, or `None` if there is no more input.
+    fn read(&mut self) -> Option<u8>;
+    fn write(&mut self, byte: u8);
+}
+
+/// The terminal.
 #[derive(Default)]
-pub struct Machine {
+pub struct Stdio;
+
+impl Console for Stdio {
+    fn read(&mut self) -> Option<u8> {
+        let mut b = [0u8; 1];
+        std::io::stdin().lock().read_exact(&mut b).ok()?;
+        Some(b[0])
+    }
+
+    fn write(&mut self, byte: u8) {
+        // As is: `char` would print bytes above 127 as two-byte UTF-8.
+        std::io::stdout().write_all(&[byte]).unwrap();
+    }
+}
+
+/// In-memory input and output for driving the machine programmatically.
+#[derive(Default)]
+pub struct Buffer {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl Buffer {
+    pub fn push_input(&mut self, input: &[u8]) {
+        self.input.extend(input);
+    }
+}
+
+impl Console for Buffer {
+    fn read(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+}
+
+/// The machine's state at some point, to go back to with [`Machine::restore`]. Console,
+/// trace and debugging settings aren't part of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    registers: [u16; 8],
+    stack: Vec<u16>,
+    mem: Vec<u16>,
+    mem_offset: usize,
+    steps: u64,
+    script_idx: usize,
+    input_log: String,
+}
+
+#[derive(Default)]
+pub struct Machine<C = Stdio> {
+    console: C,
+    mode: Mode,
     registers: [u16; 8],
     stack: Vec<u16>,
     mem: Vec<u16>,
     mem_offset: usize,
-    // DEBUG
-    // Pre-programmed input index
-    pp: Rc<[u8]>,
-    pp_idx: usize,
-    //
+    steps: u64,
+    max_steps: Option<u64>,
+    // Pre-programmed input, and the next line of it
+    script: Rc<[Line]>,
+    script_idx: usize,
+    // Set by @quiet: neither output nor script input reaches the console
+    quiet: bool,
+    // Set by @pause: input comes from the console until `!resume`
+    paused: bool,
+    // Output since the last line of input, for @expect
+    recent_output: String,
     trace_file: Option<BufWriter<File>>,
+    // Labels for the trace
+    annotations: Annotations,
+    // Where the `label` and `comment` meta-commands save to
+    annotations_path: Option<PathBuf>,
     // Watch addresses
     watches: HashMap<u16, String>,
     input_log: String,
-    // Hack
-    should_hack_teleporter: bool,
+    // Hack: address of the teleporter's `call recursive_func` and the r7 value to use
+    teleporter_hack: Option<(u16, u16)>,
+    meta_prefix: Option<String>,
+    // The rest of the input line being read, once it's known not to be a meta-command
+    pending: VecDeque<u8>,
+    saves: HashMap<String, Snapshot>,
+    breakpoints: BTreeSet<u16>,
+    // Set when stopped at a breakpoint, so that the next step goes past it
+    at_breakpoint: bool,
+}
+
+/// What a meta-command needs the op reading input to do next.
+enum Meta {
+    /// Keep reading input.
+    Done,
+    /// The machine went back to a snapshot; the op there reads the input.
+    Restored,
+    Quit,
 }
 
 impl Machine {
     pub fn new(mem: Vec<u16>) -> Self {
+        Self::with_console(mem, Stdio)
+    }
+}
+
+impl<C: Console + Default> Machine<C> {
+    pub fn with_console(mut mem: Vec<u16>, console: C) -> Self {
+        // The address space is 15 bits no matter how short the program is.
+        mem.resize(MOD as usize, 0);
         Self {
             mem,
+            console,
+            meta_prefix: Some(META_PREFIX.to_owned()),
             ..Default::default()
         }
     }
+}
+
+impl<C: Console> Machine<C> {
+    pub fn registers(&self) -> &[u16; 8] {
+        &self.registers
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn mem(&self) -> &[u16] {
+        &self.mem
+    }
+
+    /// Number of ops executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.mem_offset as u16
+    }
+
+    /// Writes `val` to `addr` from outside the program, returning what was there. Unlike
+    /// `wmem` in lenient mode, nothing is wrapped: the address has to be in the 15-bit address
+    /// space and the value a 15-bit number.
+    pub fn write_mem(&mut self, addr: u16, val: u16) -> Result<u16, Error> {
+        if addr > MAX_U15 || val > MAX_U15 {
+            return Err(Error::InvalidWrite(addr, val));
+        }
+        Ok(std::mem::replace(&mut self.mem[addr as usize], val))
+    }
 
-    pub fn set_script(&mut self, script: &[u8]) {
-        self.pp = script
-            .split_inclusive(|c| *c == b'\n')
-            .filter(|line| !line.starts_with(b"//"))
-            .flatten()
-            .cloned()
-            .collect();
+    pub fn set_register(&mut self, reg: Reg, val: u16) {
+        self.registers[reg.index()] = val;
+    }
+
+    pub fn console(&self) -> &C {
+        &self.console
+    }
+
+    pub fn console_mut(&mut self) -> &mut C {
+        &mut self.console
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Makes `script` the first input, with its directives run as they come up. See
+    /// [`crate::script`].
+    pub fn set_script(&mut self, script: Script) {
+        self.script = script.lines.into();
+        self.script_idx = 0;
     }
 
     fn jump_to_addr(&mut self, addr: u16) {
@@ -62,6 +239,16 @@ impl Machine {
         val.val(&self.registers)
     }
 
+    /// Checks an address an op at `at` reads or writes. Registers can hold any 16-bit word
+    /// loaded by `rmem`, so lenient mode wraps addresses into the 15-bit address space.
+    fn mem_addr(&self, at: u16, addr: u16) -> Result<usize, Error> {
+        match addr {
+            0..=MAX_U15 => Ok(addr as usize),
+            _ if self.mode == Mode::Strict => Err(Error::AddressOutOfRange(at, addr)),
+            _ => Ok((addr % MOD) as usize),
+        }
+    }
+
     fn set_lit(&mut self, reg: Reg, val: u16) {
         if let Some(trace) = &mut self.trace_file {
             writeln!(trace, "; set {reg} = {val}").unwrap();
@@ -77,14 +264,20 @@ impl Machine {
     /// Returns true/false whether the instruction jumped or not.
     fn apply(&mut self, op: Op) -> Result<bool, Error> {
         if let Some(trace) = &mut self.trace_file {
+            if let Some(label) = self.annotations.label(self.mem_offset as u16) {
+                writeln!(trace, "{label}:").unwrap();
+            }
             writeln!(trace, "{op}").unwrap()
         }
-        if self.mem_offset == 0x1587 && self.should_hack_teleporter {
-            println!("// DETECTIVE RECURSIVE FN ADDR");
-            println!("// BYPASSING...");
+        if let Some((call, _)) = self.teleporter_hack
+            && self.mem_offset == call as usize
+        {
+            self.print("// DETECTIVE RECURSIVE FN ADDR\n// BYPASSING...\n");
             self.set_lit(Reg::REG0, 6);
             return Ok(false);
         }
+        let addr = self.mem_offset as u16;
+        let strict = self.mode == Mode::Strict;
         let jumped = match op {
             Op::Halt => return Err(Error::Halted),
             Op::Set(a, b) => {
@@ -96,7 +289,13 @@ impl Machine {
                 false
             }
             Op::Pop(a) => {
-                let val = self.stack.pop().unwrap();
+                let Some(val) = self.stack.pop() else {
+                    return Err(if strict {
+                        Error::PoppedEmptyStack(addr)
+                    } else {
+                        Error::Halted
+                    });
+                };
                 self.set_lit(a, val);
                 false
             }
@@ -145,7 +344,12 @@ impl Machine {
                 false
             }
             Op::Mod(a, b, c) => {
-                self.set_lit(a, self.val(b) % self.val(c));
+                let val = match self.val(c) {
+                    0 if strict => return Err(Error::DivideByZero(addr)),
+                    0 => 0,
+                    c => self.val(b) % c,
+                };
+                self.set_lit(a, val);
                 false
             }
             Op::And(a, b, c) => {
@@ -161,29 +365,22 @@ impl Machine {
                 false
             }
             Op::Rmem(a, b) => {
-                let addr = self.val(b) as usize;
+                let addr = self.mem_addr(addr, self.val(b))?;
                 let mem_val = self.mem[addr];
                 self.set_lit(a, mem_val);
-                if self.watches.contains_key(&(addr as u16)) {
-                    println!(
-                        "DEBUG: read {} addr {addr} = {mem_val}",
-                        self.watches[&(addr as u16)]
-                    );
+                if let Some(name) = self.watches.get(&(addr as u16)) {
+                    let text = format!("DEBUG: read {name} addr {addr} = {mem_val}\n");
+                    self.print(&text);
                 }
                 false
             }
             Op::Wmem(a, b) => {
-                let addr = self.val(a) as usize;
-                if self.mem.len() < addr {
-                    self.mem.resize(addr - 1, 0);
-                }
+                let addr = self.mem_addr(addr, self.val(a))?;
                 let val = self.val(b);
                 self.mem[addr] = val;
-                if self.watches.contains_key(&(addr as u16)) {
-                    println!(
-                        "DEBUG: write {} addr {addr} = {val}",
-                        self.watches[&(addr as u16)]
-                    );
+                if let Some(name) = self.watches.get(&(addr as u16)) {
+                    let text = format!("DEBUG: write {name} addr {addr} = {val}\n");
+                    self.print(&text);
                 }
                 false
             }
@@ -201,46 +398,47 @@ impl Machine {
             }
             Op::Ret => {
                 let Some(popped) = self.stack.pop() else {
-                    return Err(Error::PoppedEmptyStack);
+                    return Err(Error::Halted);
                 };
                 self.jump_to_addr(popped);
                 true
             }
             Op::Out(a) => {
-                print!("{}", self.val(a) as u8 as char);
+                let val = self.val(a);
+                if strict && !u8::try_from(val).is_ok_and(|b| b.is_ascii()) {
+                    return Err(Error::NonAsciiOutput(addr, val));
+                }
+                self.recent_output.push(val as u8 as char);
+                if !self.quiet {
+                    self.console.write(val as u8);
+                }
                 false
             }
             Op::In(a) => {
-                let input = if self.pp_idx < self.pp.len() {
-                    let c = self.pp[self.pp_idx];
-                    let ch = char::from_u32(c as u32).unwrap();
-                    print!("{}", ch);
-                    self.input_log.push(ch);
-                    self.pp_idx += 1;
-                    c as u16
-                } else {
-                    let mut b = [0u8; 1];
-                    std::io::stdin()
-                        .lock()
-                        .read_exact(&mut b)
-                        .expect("failed to read input char");
-                    self.input_log.push(char::from_u32(b[0] as u32).unwrap());
-                    b[0] as u16
-                };
-
-                let code = "use teleporter\n";
-                if self.input_log.len() >= code.len()
-                    && &self.input_log[(self.input_log.len() - code.len())..] == code
-                {
-                    if let Some(trace) = &mut self.trace_file {
-                        writeln!(trace, ";; USING TELEPORTER").unwrap();
+                let input = loop {
+                    if self.pending.is_empty()
+                        && (self.input_log.is_empty() || self.input_log.ends_with('\n'))
+                    {
+                        self.read_line()?;
+                        if let Some(line) = self.meta_line() {
+                            self.pending.clear();
+                            match self.run_meta(&line) {
+                                Meta::Done => continue,
+                                Meta::Restored => return Ok(true),
+                                Meta::Quit => return Err(Error::Halted),
+                            }
+                        }
                     }
-                    if self.should_hack_teleporter {
-                    self.set_lit(Reg::REG7, R7_VAL);
+                    match self.pending.pop_front().or_else(|| self.console.read()) {
+                        Some(b) => break b,
+                        None => return Err(Error::InputExhausted(addr)),
                     }
+                };
+                if strict && !input.is_ascii() {
+                    return Err(Error::NonAsciiInput(addr, input));
                 }
-
-                self.set_lit(a, input);
+                self.input_log.push(input as char);
+                self.set_lit(a, input as u16);
                 false
             }
             Op::Noop => false,
@@ -248,56 +446,413 @@ impl Machine {
         Ok(jumped)
     }
 
-    pub fn run(&mut self) {
-        loop {
-            let op = match Op::try_from(&self.mem[self.mem_offset..]) {
-                Ok(op) => op,
-                Err(err) => panic!(
-                    "failed to parse opcode at offset {}: {err:?}",
-                    self.mem_offset
-                ),
+    /// Reads the next input line, up to and including its newline, into `pending`: from the
+    /// script while it lasts and isn't paused, echoing it, then from the console. Directives
+    /// before the line are run first.
+    fn read_line(&mut self) -> Result<(), Error> {
+        while !self.paused {
+            let Some(line) = self.script.get(self.script_idx).cloned() else {
+                self.quiet = false;
+                break;
             };
-            // println!("{op}");
-            let offset = 1 + op.arg_count();
-            let jumped = match self.apply(op) {
-                Ok(jumped) => jumped,
+            self.script_idx += 1;
+            match line.item.clone() {
+                Item::Input(input) => {
+                    self.pending.extend(input.bytes().chain([b'\n']));
+                    if !self.quiet {
+                        self.print(&input);
+                        self.print("\n");
+                    }
+                    self.recent_output.clear();
+                    // Show what the last line of the script leads to.
+                    if self.script_idx == self.script.len() {
+                        self.quiet = false;
+                    }
+                    return Ok(());
+                }
+                Item::Expect(text) => {
+                    if !self.recent_output.contains(&text) {
+                        let err = line.error(format!("expected {text:?} in the output"));
+                        return Err(Error::Script(err));
+                    }
+                }
+                Item::SetReg(reg, val) => {
+                    let val = match val {
+                        Some(val) => val,
+                        None => self.hack_r7().map_err(|e| Error::Script(line.error(e)))?,
+                    };
+                    self.set_register(reg, val);
+                }
+                Item::Patch(addr, vals) => {
+                    for (i, val) in vals.into_iter().enumerate() {
+                        self.write_mem(addr.wrapping_add(i as u16), val)
+                            .map_err(|e| Error::Script(line.error(e.to_string())))?;
+                    }
+                }
+                Item::Snapshot(name) => {
+                    self.saves.insert(name, self.snapshot());
+                }
+                Item::Pause => {
+                    self.paused = true;
+                    self.quiet = false;
+                }
+                Item::Quiet => self.quiet = true,
+                Item::Echo => self.quiet = false,
+            }
+        }
+        while let Some(b) = self.console.read() {
+            self.pending.push_back(b);
+            if b == b'\n' {
+                break;
+            }
+        }
+        self.recent_output.clear();
+        Ok(())
+    }
+
+    /// The meta-command on the line in `pending`, if it is one.
+    fn meta_line(&self) -> Option<String> {
+        let prefix = self.meta_prefix.as_deref()?.as_bytes();
+        let line: Vec<u8> = self.pending.iter().copied().collect();
+        let command = line.strip_prefix(prefix)?;
+        Some(String::from_utf8_lossy(command).trim().to_owned())
+    }
+
+    fn print(&mut self, text: &str) {
+        for b in text.bytes() {
+            self.console.write(b);
+        }
+    }
+
+    /// Runs a meta-command, given without the prefix, as if it had been typed. Quitting is
+    /// reported as [`Error::Halted`].
+    ///
+    /// ```text
+    /// state                       the room, inventory and item locations, see crate::state
+    /// teleport, give, set-flag    cheats, see crate::cheat
+    /// save [<name>]               take a snapshot
+    /// load [<name>]               go back to a snapshot
+    /// regs                        show the registers
+    /// setreg <reg> <value>        set a register, e.g. `setreg r7 25734`
+    /// setreg r7                   set r7 to the teleporter hack's value
+    /// break [<addr|name>]         set or clear a breakpoint, or list them
+    /// label <addr> <name>         label <addr> in the annotations file
+    /// comment <addr> <text>       add a comment to <addr> in the annotations file; the text
+    ///                             can be quoted
+    /// trace on [<file>] | off     trace every op to <file> [default: run.trace]
+    /// resume                      go on with the script after its @pause
+    /// quit                        stop the machine
+    /// ```
+    pub fn meta(&mut self, line: &str) -> Result<(), Error> {
+        match self.run_meta(line) {
+            Meta::Quit => Err(Error::Halted),
+            Meta::Done | Meta::Restored => Ok(()),
+        }
+    }
+
+    fn run_meta(&mut self, line: &str) -> Meta {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Meta::Done;
+        };
+        let mut next = Meta::Done;
+        let result = match (name, args) {
+            ("state", []) => self.state().map(|state| state.to_string()),
+            ("teleport" | "give" | "set-flag", _) => line
+                .parse()
+                .and_then(|c| cheat::apply(self, &c))
+                .map(|done| done + "\n"),
+            ("save", [] | [_]) => {
+                let slot = args.first().copied().unwrap_or("default");
+                self.saves.insert(slot.to_owned(), self.snapshot());
+                Ok(format!("saved {slot}\n"))
+            }
+            ("load", [] | [_]) => {
+                let slot = args.first().copied().unwrap_or("default");
+                match self.saves.get(slot).cloned() {
+                    Some(snapshot) => {
+                        self.restore(&snapshot);
+                        next = Meta::Restored;
+                        Ok(format!("loaded {slot}\n"))
+                    }
+                    None => Err(format!("nothing saved as {slot}")),
+                }
+            }
+            ("regs", []) => {
+                let regs: Vec<_> = (0..8)
+                    .map(|i| format!("r{i} = {}", self.registers[i]))
+                    .collect();
+                Ok(format!(
+                    "{}\npc = 0x{:04x}, stack depth {}, {} steps\n",
+                    regs.join(", "),
+                    self.mem_offset,
+                    self.stack.len(),
+                    self.steps
+                ))
+            }
+            ("setreg", ["r7"]) => self.hack_r7().map(|r7| {
+                self.set_register(Reg::REG7, r7);
+                format!("r7 = {r7}\n")
+            }),
+            ("setreg", [reg, val]) => match (reg.parse::<Reg>(), val.parse::<u16>()) {
+                (Err(_), _) => Err(format!("invalid register {reg:?}")),
+                (_, Ok(MOD..) | Err(_)) => Err(format!("invalid value {val:?}")),
+                (Ok(reg), Ok(val)) => {
+                    self.set_register(reg, val);
+                    Ok(format!("{reg} = {val}\n"))
+                }
+            },
+            ("break", []) => {
+                let addrs: Vec<_> = self
+                    .breakpoints
+                    .iter()
+                    .map(|a| format!("0x{a:04x}\n"))
+                    .collect();
+                Ok(addrs.concat())
+            }
+            ("break", [at]) => self.resolve(at).map(|addr| {
+                if self.breakpoints.remove(&addr) {
+                    format!("cleared the breakpoint at 0x{addr:04x}\n")
+                } else {
+                    self.breakpoints.insert(addr);
+                    format!("breakpoint at 0x{addr:04x}\n")
+                }
+            }),
+            ("label", [at, label]) => self.resolve(at).and_then(|addr| {
+                self.annotate(|file| file.set_label(addr, label))?;
+                Ok(format!("labeled 0x{addr:04x} {label}\n"))
+            }),
+            ("comment", [at, _, ..]) => {
+                let text = line.trim()[name.len()..].trim_start()[at.len()..].trim();
+                let text = text
+                    .strip_prefix('"')
+                    .and_then(|t| t.strip_suffix('"'))
+                    .unwrap_or(text);
+                self.resolve(at).and_then(|addr| {
+                    self.annotate(|file| file.add_comment(addr, text))?;
+                    Ok(format!("commented 0x{addr:04x}\n"))
+                })
+            }
+            ("trace", ["on", rest @ ..]) if rest.len() <= 1 => {
+                let path = rest.first().copied().unwrap_or("run.trace");
+                self.set_trace_out(Path::new(path))
+                    .map(|()| format!("tracing to {path}\n"))
+                    .map_err(|e| format!("{path}: {e}"))
+            }
+            ("trace", ["off"]) => {
+                if let Some(mut trace) = self.trace_file.take() {
+                    let _ = trace.flush();
+                }
+                Ok("tracing off\n".to_owned())
+            }
+            ("resume", []) if self.paused => {
+                self.paused = false;
+                Ok("resuming the script\n".to_owned())
+            }
+            ("resume", []) => Err("the script isn't paused".to_owned()),
+            ("quit", []) => return Meta::Quit,
+            (
+                "state" | "save" | "load" | "regs" | "setreg" | "break" | "label" | "comment"
+                | "trace" | "resume" | "quit",
+                _,
+            ) => Err("wrong arguments".to_owned()),
+            _ => {
+                self.print(&format!("unknown meta-command {line:?}\n"));
+                return Meta::Done;
+            }
+        };
+        let out = match result {
+            Ok(out) => out,
+            Err(e) => format!("{name}: {e}\n"),
+        };
+        self.print(&out);
+        next
+    }
+
+    /// Changes the annotations file with `change` and saves it, keeping the machine's
+    /// annotations in step with it.
+    fn annotate(
+        &mut self,
+        change: impl FnOnce(&mut AnnotationsFile) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let path = self
+            .annotations_path
+            .as_deref()
+            .ok_or("no annotations file to save to")?;
+        let mut file = AnnotationsFile::open(path).map_err(|e| e.to_string())?;
+        change(&mut file)?;
+        file.save()
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        self.annotations = file.annotations().clone();
+        Ok(())
+    }
+
+    /// An address in hex, or a name from the annotations.
+    fn resolve(&self, at: &str) -> Result<u16, String> {
+        u16::from_str_radix(at.trim_start_matches("0x"), 16)
+            .ok()
+            .or_else(|| self.annotations.address_of(at))
+            .ok_or_else(|| format!("no address or name {at:?}"))
+    }
+
+    /// The game's state, decoded with the layout in the annotations. See [`crate::state`].
+    pub fn state(&self) -> Result<State, String> {
+        Layout::from_annotations(&self.annotations)?.decode(&self.mem)
+    }
+
+    /// Decodes and executes a single instruction. Halting is reported as [`Error::Halted`].
+    pub fn step(&mut self) -> Result<(), Error> {
+        if self.max_steps.is_some_and(|max| self.steps >= max) {
+            return Err(Error::StepLimit(self.steps));
+        }
+        let pc = self.mem_offset as u16;
+        if self.breakpoints.contains(&pc) && !self.at_breakpoint {
+            self.at_breakpoint = true;
+            return Err(Error::Breakpoint(pc));
+        }
+        self.at_breakpoint = false;
+        let op = Op::try_from(self.mem.get(self.mem_offset..).unwrap_or_default())
+            .map_err(|err| Error::Decode(self.mem_offset as u16, Box::new(err)))?;
+        // println!("{op}");
+        let offset = 1 + op.arg_count();
+        if !self.apply(op)? {
+            self.mem_offset += offset;
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<(), Error> {
+        loop {
+            match self.step() {
+                Ok(()) => {}
                 Err(Error::Halted) => {
-                    return self.stop();
+                    self.stop();
+                    return Ok(());
+                }
+                Err(err) => {
+                    self.flush_trace();
+                    return Err(err);
                 }
-                Err(err) => panic!("{:?}", err),
-            };
-            if !jumped {
-                self.mem_offset += offset;
             }
         }
     }
 
     pub fn stop(&mut self) {
-        println!("Game Over");
+        self.print("Game Over\n");
+        self.flush_trace();
+    }
+
+    fn flush_trace(&mut self) {
         if let Some(trace) = &mut self.trace_file {
             let _ = trace.flush();
         }
     }
 
-    pub fn set_trace_out(&mut self, filename: &str) {
+    pub fn set_trace_out(&mut self, path: &Path) -> std::io::Result<()> {
         let trace_file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(filename)
-            .unwrap();
+            .open(path)?;
         self.trace_file = Some(BufWriter::new(trace_file));
+        Ok(())
+    }
+
+    /// Makes `run` stop with [`Error::StepLimit`] once it has executed `max` ops in total.
+    pub fn set_max_steps(&mut self, max: Option<u64>) {
+        self.max_steps = max;
+    }
+
+    pub fn annotations(&self) -> &Annotations {
+        &self.annotations
+    }
+
+    pub fn set_annotations(&mut self, annotations: Annotations) {
+        self.annotations = annotations;
+    }
+
+    /// Makes the `label` and `comment` meta-commands write to the annotations file at `path`.
+    /// They reload the machine's annotations from it.
+    pub fn set_annotations_path(&mut self, path: &Path) {
+        self.annotations_path = Some(path.to_owned());
     }
 
     pub fn watch(&mut self, addr: u16, name: &str) {
         self.watches.insert(addr, name.to_owned());
     }
 
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            stack: self.stack.clone(),
+            mem: self.mem.clone(),
+            mem_offset: self.mem_offset,
+            steps: self.steps,
+            script_idx: self.script_idx,
+            input_log: self.input_log.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.registers = snapshot.registers;
+        self.stack.clone_from(&snapshot.stack);
+        self.mem.clone_from(&snapshot.mem);
+        self.mem_offset = snapshot.mem_offset;
+        self.steps = snapshot.steps;
+        self.script_idx = snapshot.script_idx;
+        self.input_log.clone_from(&snapshot.input_log);
+    }
+
     pub fn set_eighth_register(&mut self, val: u16) {
         self.registers[7] = val;
     }
 
-    pub fn hack_teleporter(&mut self) {
-        self.should_hack_teleporter = !self.should_hack_teleporter;
+    /// Skips the `call` at `call_addr` whenever it's reached, as if it returned 6, whatever r7
+    /// holds. `!setreg r7` and `@setreg r7` without a value set r7 to `r7`. Both come from the
+    /// ROM's [`crate::rom::Profile`].
+    pub fn hack_teleporter(&mut self, call_addr: u16, r7: u16) {
+        self.teleporter_hack = Some((call_addr, r7));
+    }
+
+    /// The r7 value given to [`Machine::hack_teleporter`].
+    fn hack_r7(&self) -> Result<u16, String> {
+        self.teleporter_hack
+            .map(|(_, r7)| r7)
+            .ok_or_else(|| "r7 needs a value without the teleporter hack".to_owned())
+    }
+
+    /// Sets what input lines holding meta-commands start with. `None` turns them off.
+    pub fn set_meta_prefix(&mut self, prefix: Option<&str>) {
+        self.meta_prefix = prefix.map(str::to_owned);
+    }
+
+    /// Makes [`Machine::step`] stop with [`Error::Breakpoint`] before executing the op at
+    /// `addr`. Stepping again goes on from there.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+}
+
+impl Machine<Buffer> {
+    /// Types `line` and runs until the machine wants more input, returning what it printed
+    /// meanwhile. Output from before is discarded. Unlike [`Machine::run`], halting is
+    /// reported as [`Error::Halted`].
+    pub fn command(&mut self, line: &str) -> Result<String, Error> {
+        self.console.input.clear();
+        self.console.output.clear();
+        self.console.push_input(line.as_bytes());
+        self.console.push_input(b"\n");
+        loop {
+            match self.step() {
+                Ok(()) => {}
+                Err(Error::InputExhausted(_)) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(String::from_utf8_lossy(&self.console.output).into_owned())
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -1,213 +1,476 @@
 use std::{
-    collections::HashMap,
-    fmt::Write,
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
-    rc::Rc,
+    error::Error,
+    io::Read,
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-use crate::machine::{MAX_U15, MOD, Machine};
-
-pub mod error;
-pub mod machine;
-pub mod op;
-
-const BIN_PATH: &str = "challenge.bin";
-
-#[derive(Clone, Debug)]
-enum Annotation {
-    Comment(String),
-    Label(String),
-}
-
-enum AnnotationSection {
-    Unknown,
-    Comments,
-    Labels,
-}
-
-#[derive(Debug)]
-struct ParseAnnotationsError;
-impl std::fmt::Display for ParseAnnotationsError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "failed to parse annotation file")
-    }
-}
-impl std::error::Error for ParseAnnotationsError {}
-
-type Annotations = HashMap<u16, Vec<Annotation>>;
-
-fn load_annotations(path: &Path) -> Result<Option<Annotations>, Box<dyn std::error::Error>> {
-    if !std::fs::exists(path)? {
-        return Ok(None);
-    }
-    let reader = BufReader::new(File::open(path)?);
-    let mut section = AnnotationSection::Unknown;
-    let mut annotations = Annotations::new();
-    for line in reader.lines() {
-        match line?.as_str() {
-            "[comments]" => section = AnnotationSection::Comments,
-            "[labels]" => section = AnnotationSection::Labels,
-            l => {
-                let Some((left, right)) = l.split_once(" = ") else {
-                    continue;
-                };
-                let addr = u16::from_str_radix(left.trim_start_matches("0x"), 16)?;
-                let text = right.strip_prefix("\"").and_then(|s| s.strip_suffix("\""));
-                let Some(text) = text else {
-                    continue;
-                };
-                match section {
-                    AnnotationSection::Comments => {
-                        annotations
-                            .entry(addr)
-                            .and_modify(|v| v.push(Annotation::Comment(text.to_owned())))
-                            .or_insert_with(|| vec![Annotation::Comment(text.to_owned())]);
+use cli::{Annotate, Cli, Command, MapFormat, PlayOptions, Puzzle, RunOptions};
+use vmc::{
+    ackermann,
+    annotations::{Annotations, AnnotationsFile, load_annotations},
+    codes::{self, Hashes},
+    coins,
+    disasm::Options,
+    error::Error as VmError,
+    explore::Map,
+    functions::{Functions, regs},
+    machine::{Buffer, Console, MOD, Machine, load_rom},
+    orb,
+    rom::{self, Profile},
+    script::Script,
+    signature::{self, Match, Signature},
+    state::Layout,
+    xref::{Ref, Xrefs},
+};
+
+mod cli;
+
+const ANNOTATIONS_PATH: &str = "annotations.ini";
+
+fn annotations_path(cli: &Cli) -> PathBuf {
+    cli.annotations
+        .clone()
+        .unwrap_or_else(|| ANNOTATIONS_PATH.into())
+}
+
+/// Annotations from `--annotations`, which must exist, or from `annotations.ini` if it does.
+fn annotations(cli: &Cli) -> Result<Annotations, Box<dyn Error>> {
+    let path = annotations_path(cli);
+    match load_annotations(&path)? {
+        Some(annotations) => Ok(annotations),
+        None if cli.annotations.is_none() => Ok(Annotations::default()),
+        None => Err(format!("{}: no such file", path.display()).into()),
+    }
+}
+
+fn rom(cli: &Cli) -> Result<Vec<u16>, Box<dyn Error>> {
+    load_rom(&cli.rom).map_err(|e| format!("{}: {e}", cli.rom.display()).into())
+}
+
+fn decompile(cli: &Cli, high_level: bool, options: &Options) -> Result<(), Box<dyn Error>> {
+    let mem = rom(cli)?;
+    let annotations = annotations(cli)?;
+    let mut out = String::new();
+    if high_level {
+        vmc::pseudo::decompile(&mem, &annotations, &mut out)?;
+    } else {
+        vmc::disasm::decompile(&mem, &annotations, options, &mut out)?;
+    }
+    print!("{out}");
+    Ok(())
+}
+
+/// Adds labels or comments and saves them back to the annotations file.
+fn annotate(cli: &Cli, action: &Annotate) -> Result<(), Box<dyn Error>> {
+    let mut file = AnnotationsFile::open(&annotations_path(cli))?;
+    match action {
+        Annotate::Label(addr, name) => file.set_label(*addr, name)?,
+        Annotate::Comment(addr, text) => file.add_comment(*addr, text)?,
+        Annotate::Auto => {
+            let added = file.auto_label(&rom(cli)?);
+            println!("added {added} labels");
+        }
+    }
+    file.save()?;
+    Ok(())
+}
+
+fn run(cli: &Cli, opts: &RunOptions) -> Result<(), Box<dyn Error>> {
+    let mem = rom(cli)?;
+    let profile = rom::detect(&mem);
+    let mut machine = Machine::new(mem);
+    if let Some(path) = &opts.script {
+        machine.set_script(Script::load(path)?);
+    }
+    machine.set_annotations(annotations(cli)?);
+    machine.set_annotations_path(&annotations_path(cli));
+    if let Some(path) = &opts.trace_out {
+        machine
+            .set_trace_out(path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+    if opts.hack_teleporter {
+        println!("HACKS ENABLED");
+        hack_teleporter(&mut machine, &profile)?;
+    }
+    if let Some(prefix) = &opts.meta_prefix {
+        machine.set_meta_prefix(Some(prefix.as_str()).filter(|p| !p.is_empty()));
+    }
+    machine.set_mode(opts.mode);
+    machine.set_max_steps(opts.max_steps);
+    loop {
+        match machine.run() {
+            Err(VmError::Breakpoint(addr)) => {
+                println!(
+                    "breakpoint at 0x{addr:04x}; meta-commands without the prefix, then `continue`"
+                );
+                for line in std::io::stdin().lines() {
+                    let line = line?;
+                    if line.trim() == "continue" {
+                        break;
                     }
-                    AnnotationSection::Labels => {
-                        annotations
-                            .entry(addr)
-                            .and_modify(|v| v.push(Annotation::Label(text.to_owned())))
-                            .or_insert_with(|| vec![Annotation::Label(text.to_owned())]);
+                    if let Err(VmError::Halted) = machine.meta(&line) {
+                        machine.stop();
+                        return Ok(());
                     }
-                    AnnotationSection::Unknown => return Err(ParseAnnotationsError.into()),
-                };
+                }
             }
+            result => return Ok(result?),
         }
     }
-    Ok(Some(annotations))
 }
 
-fn decompile(mem: &[u16]) {
-    let annotations = load_annotations(Path::new("annotations.ini")).unwrap();
-    let mut addr = 0;
-    let mut in_data = false;
-    while addr < mem.len() {
-        match crate::op::Op::try_from(&mem[addr..]) {
-            Ok(op) => {
-                let mut out_line = String::new();
-                if in_data {
-                    // println!("\n\nops:");
-                    println!("\n");
-                    in_data = false;
-                }
-                let addr_annos = annotations.as_ref().and_then(|m| m.get(&(addr as u16)));
-                if let Some(list) = addr_annos {
-                    list.iter().for_each(|a| {
-                        if let Annotation::Label(text) = a {
-                            println!("{text}:");
-                        }
-                    })
-                }
-                write!(out_line, "/* 0x{addr:04x} */ {op}").unwrap();
-                if let Some(list) = addr_annos {
-                    list.iter().for_each(|a| {
-                        if let Annotation::Comment(text) = a {
-                            for _ in out_line.len()..40 {
-                                write!(out_line, " ").unwrap();
-                            }
-                            write!(out_line, "; {text}").unwrap();
-                        }
-                    })
-                }
-                println!("{out_line}");
-                addr += 1 + op.arg_count();
-            }
-            Err(_err) => {
-                if !in_data {
-                    in_data = true;
-                    // println!("\ndata:");
-                    print!("; binary data omitted");
-                }
-                // print!("{}", ((mem[offset] >> 8) as u8).escape_ascii());
-                // print!("{}", ((mem[offset] & 0xFF) as u8).escape_ascii());
-                addr += 1;
-                continue;
-            }
+fn info(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let mem = rom(cli)?;
+    let profile = rom::detect(&mem);
+    let show = |v: Option<u16>| v.map_or("unknown".to_owned(), |v| format!("0x{v:04x}"));
+    println!("rom:             {}", cli.rom.display());
+    println!("fingerprint:     {:016x}", rom::fingerprint(&mem));
+    println!("variant:         {}", profile.name);
+    println!("teleporter call: {}", show(profile.teleporter_call));
+    println!("recursive_func:  {}", show(profile.recursive_func));
+    println!("current room:    {}", show(profile.current_room));
+    match profile.r7 {
+        Some(r7) => println!("r7:              {r7}"),
+        None => println!("r7:              unknown, see `vmc reg8`"),
+    }
+    Ok(())
+}
+
+fn scan(cli: &Cli, pattern: Option<&str>, label: bool) -> Result<(), Box<dyn Error>> {
+    let mem = rom(cli)?;
+    let show = |m: &Match| {
+        let captures: Vec<_> = m.captures.iter().map(|c| format!("0x{c:04x}")).collect();
+        match captures.is_empty() {
+            true => format!("0x{:04x}", m.addr),
+            false => format!("0x{:04x} [{}]", m.addr, captures.join(", ")),
+        }
+    };
+    if let Some(pattern) = pattern {
+        let sig: Signature = pattern.parse()?;
+        for m in sig.scan(&mem) {
+            println!("{}", show(&m));
+        }
+        return Ok(());
+    }
+    let mut file = label
+        .then(|| AnnotationsFile::open(&annotations_path(cli)))
+        .transpose()?;
+    for (name, _) in signature::KNOWN {
+        let Some(m) = signature::find(&mem, name) else {
+            println!("{name:<20} not found");
+            continue;
         };
+        println!("{name:<20} {}", show(&m));
+        if let Some(file) = &mut file
+            && !file.annotations().labels.contains_key(&m.addr)
+        {
+            file.set_label(m.addr, name)?;
+        }
+    }
+    if let Some(file) = file {
+        file.save()?;
     }
+    Ok(())
 }
 
-fn calc_reg_8() {
-    /// Non-literal implementation of `recursive_function` with memoization.
-    /// See `./teleporter.py` for notes and derivation.
-    fn recfn(r0: u16, r1: u16, r7: u16) -> u16 {
-        match (r0, r1) {
-            (0, n) => (n + 1).rem_euclid(MOD),
-            (m, 0) => recfn(m - 1, r7, r7),
-            (1, n) => (r7 + n + 1).rem_euclid(MOD),
-            (2, n) => (r7 * (n + 2) + n + 1).rem_euclid(MOD),
-            (3, n) => {
-                //                 n ⎛  3       2           ⎞
-                // -2⋅r₇ + (r₇ + 1) ⋅⎝r₇  + 3⋅r₇  + 3⋅r₇ + 1⎠ - 1
-                // ──────────────────────────────────────────────
-                //                       r₇
-                //
-                // Base case for recurrence:
-                // A(2, n, r7) = r7 * (n + 2) + n + 1
-                // A(3, n, r7) = ack(2, ack(3, n - 1))
-                //             = r7 * (ack(3, n-1) + 2) + ack(3, n-1) + 1
-                //             = (r7 + 1) * ack(3, n-1) + 2*r7  + 1
-                // A(3, 0, r7) = r7 * (r7 + 2) + r7 + 1
-                //             = r7^2 + 3*r7 + 1
-                let base = (r7 * r7 + 3 * r7 + 1).rem_euclid(MOD);
-                // Recurrence coefficients:
-                // A(2, x, r7) = r7*(x + 2) + x + 1
-                //             = (r7 + 1)*x + (2*r7 + 1)
-                let mul = (r7 + 1).rem_euclid(MOD);
-                let add = (2 * r7 + 1).rem_euclid(MOD);
-                // instead of (k + 1)^n, do it in n steps
-                (0..n).fold(base, |acc, _| (mul * acc + add).rem_euclid(MOD))
+fn functions(cli: &Cli, export: bool) -> Result<(), Box<dyn Error>> {
+    let mem = rom(cli)?;
+    let funcs = Functions::analyze(&mem, &annotations(cli)?);
+    for f in funcs.functions.values() {
+        let mut line = format!("0x{:04x} {}{}", f.entry, f.name, f.signature());
+        if f.clobbers() != 0 {
+            let regs: Vec<_> = regs(f.clobbers()).map(|r| r.to_string()).collect();
+            line += &format!(", clobbers {}", regs.join(", "));
+        }
+        line += &format!(", stack depth {}", f.stack.max_depth);
+        if f.stack.effect.is_none() {
+            line += ", never returns";
+        }
+        println!("{line}");
+        for issue in &f.stack.issues {
+            println!("    {issue}");
+        }
+    }
+    if export {
+        let mut file = AnnotationsFile::open(&annotations_path(cli))?;
+        let mut added = 0;
+        for f in funcs.functions.values() {
+            if file.annotations().functions.contains_key(&f.entry) {
+                continue;
             }
-            (m, n) => recfn(m - 1, recfn(m, n - 1, r7), r7),
+            file.add_function(&funcs.annotation(f))?;
+            added += 1;
         }
+        file.save()?;
+        println!("added {added} functions");
     }
+    Ok(())
+}
 
-    for r7 in 1..=MAX_U15 {
-        if recfn(4, 1, r7) == 6 {
-            println!("FOUND r7 = {r7}");
-            return;
+fn xref(cli: &Cli, target: &str, boot: bool) -> Result<(), Box<dyn Error>> {
+    let mut mem = rom(cli)?;
+    if boot {
+        let mut machine = Machine::with_console(mem, Buffer::default());
+        match machine.run() {
+            Err(VmError::InputExhausted(_)) => {}
+            Ok(()) => return Err("--boot: the ROM halted before asking for input".into()),
+            Err(e) => return Err(format!("--boot: {e}").into()),
         }
+        mem = machine.mem().to_vec();
+    }
+    let annotations = annotations(cli)?;
+    let xrefs = Xrefs::build(&mem, &annotations);
+    let addr = annotations
+        .address_of(target)
+        .or_else(|| {
+            let mut names = xrefs.function_names.iter();
+            names.find(|(_, n)| *n == target).map(|(a, _)| *a)
+        })
+        .or_else(|| u16::from_str_radix(target.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| format!("{target:?} is neither an address nor a known name"))?;
+
+    let name = |addr: u16| {
+        annotations
+            .labels
+            .get(&addr)
+            .or_else(|| xrefs.function_names.get(&addr))
+            .map_or(String::new(), |n| format!(" {n}"))
+    };
+    let show = |addr: u16, r: &Ref| {
+        let function = r.function.map_or(String::new(), |f| {
+            format!(" in {}", xrefs.function_names[&f])
+        });
+        println!("  {:<8} 0x{addr:04x}{}{function}", r.kind, name(addr));
+    };
+    println!("0x{addr:04x}{}", name(addr));
+    if let Some(s) = xrefs.strings.get(&addr) {
+        println!("string {s:?}");
+    }
+    println!("references to it:");
+    for r in xrefs.to(addr) {
+        show(r.from, r);
     }
-    println!("NOTHING FOUND");
+    if !xrefs.from(addr).is_empty() {
+        println!("references from it:");
+        for r in xrefs.from(addr) {
+            show(r.to, r);
+        }
+    }
+    Ok(())
 }
 
-fn load_mem(path: &Path) -> Vec<u16> {
-    let rom_data = std::fs::read(path).unwrap();
-    let (chunks, _) = rom_data.as_chunks::<2>();
-    chunks.iter().cloned().map(u16::from_le_bytes).collect()
+/// Skips the teleporter's check with the call and r7 from `profile`.
+fn hack_teleporter<C: Console>(
+    machine: &mut Machine<C>,
+    profile: &Profile,
+) -> Result<(), Box<dyn Error>> {
+    let (Some(call), Some(r7)) = (profile.teleporter_call, profile.r7) else {
+        return Err(format!(
+            "--hack-teleporter: no teleporter check or r7 value known for {}",
+            profile.name
+        )
+        .into());
+    };
+    machine.hack_teleporter(call, r7);
+    Ok(())
 }
 
-fn main() {
-    let args: Rc<[String]> = std::env::args().skip(1).collect();
-    match args.first().map(|s| s.as_str()) {
-        Some("decompile") => {
-            let mem = load_mem(Path::new(BIN_PATH));
-            decompile(&mem);
+/// A machine waiting for input at the start of the game, or where the script in `opts` leaves
+/// off.
+fn play(cli: &Cli, opts: &PlayOptions) -> Result<Machine<Buffer>, Box<dyn Error>> {
+    let mem = rom(cli)?;
+    let profile = rom::detect(&mem);
+    let mut machine = Machine::with_console(mem, Buffer::default());
+    machine.set_annotations(annotations(cli)?);
+    if opts.hack_teleporter {
+        hack_teleporter(&mut machine, &profile)?;
+    }
+    if let Some(path) = &opts.script {
+        machine.set_script(Script::load(path)?);
+    }
+    match machine.run() {
+        Err(VmError::InputExhausted(_)) => Ok(machine),
+        Ok(()) => Err("the game ended before asking for input".into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Explores from the start of the game, or from where the script in `opts` leaves off.
+fn map(cli: &Cli, opts: &PlayOptions) -> Result<Map, Box<dyn Error>> {
+    let mut machine = play(cli, opts)?;
+    let profile = rom::detect(&rom(cli)?);
+    Ok(vmc::explore::explore(&mut machine, profile.current_room)?)
+}
+
+fn explore(cli: &Cli, opts: &PlayOptions, format: MapFormat) -> Result<(), Box<dyn Error>> {
+    let map = map(cli, opts)?;
+    match format {
+        MapFormat::Json => print!("{}", map.to_json()),
+        MapFormat::Dot => print!("{}", map.to_dot()),
+    }
+    Ok(())
+}
+
+fn route(cli: &Cli, opts: &PlayOptions, from: &str, to: &str) -> Result<(), Box<dyn Error>> {
+    let map = map(cli, opts)?;
+    let rooms = |room: &str| match room.parse::<usize>() {
+        Ok(id) if id < map.rooms.len() => Ok(vec![id]),
+        _ => match map.find(room) {
+            ids if ids.is_empty() => Err(format!("no room {room:?}")),
+            ids => Ok(ids),
+        },
+    };
+    let from = match rooms(from)?[..] {
+        [id] => id,
+        ref ids => {
+            let ids: Vec<_> = ids.iter().map(|id| id.to_string()).collect();
+            return Err(format!(
+                "{from:?} is ambiguous, use one of the ids {}",
+                ids.join(", ")
+            )
+            .into());
         }
-        Some("reg8") => calc_reg_8(),
-        None | Some("run") => {
-            let mem = load_mem(Path::new(BIN_PATH));
-            let mut machine = Machine::new(mem);
-            if let Some((i, _)) = args
-                .iter()
-                .enumerate()
-                .find(|(_, x)| x.as_str() == "--script")
-            {
-                let script =
-                    std::fs::read(args.get(i + 1).expect("usage: --script <file>")).unwrap();
-                machine.set_script(&script);
-            }
-            if args.contains(&"--trace".to_owned()) {
-                machine.set_trace_out("run.trace");
-            }
-            if args.contains(&"--hack-teleporter".to_owned()) {
-                println!("HACKS ENABLED");
-                machine.hack_teleporter();
+    };
+    let route = rooms(to)?
+        .into_iter()
+        .filter_map(|to| map.route(from, to))
+        .min_by_key(Vec::len)
+        .ok_or_else(|| format!("{to:?} can't be reached from {}", map.rooms[from].name))?;
+    print!("{}", map.script(from, &route));
+    Ok(())
+}
+
+fn solve(
+    cli: &Cli,
+    puzzle: Puzzle,
+    opts: &PlayOptions,
+    grid: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    match puzzle {
+        Puzzle::Coins => {
+            let mut machine = play(cli, opts)?;
+            let (equation, coins) = coins::read(&mut machine)?;
+            let order = coins::solve(&equation, &coins)
+                .ok_or_else(|| format!("no order of the coins solves {equation}"))?;
+            let values: Vec<_> = order.iter().map(|(_, v)| *v).collect();
+            println!("// {equation}");
+            println!("// {}", equation.fill(&values));
+            for (name, _) in order {
+                println!("use {name}");
             }
-            machine.run();
         }
-        Some(cmd) => println!("Unknown command: {cmd}"),
+        Puzzle::Orb => {
+            let grid: orb::Grid = match grid {
+                Some(path) => std::fs::read_to_string(path)
+                    .map_err(|e| format!("{}: {e}", path.display()))?
+                    .parse()
+                    .map_err(|e| format!("{}: {e}", path.display()))?,
+                None => orb::read(&mut play(cli, opts)?)?,
+            };
+            let steps =
+                orb::solve(&grid).ok_or("the orb can't get to the door at the right weight")?;
+            print!("{}", orb::script(&grid, &steps));
+        }
+    }
+    Ok(())
+}
+
+fn state(cli: &Cli, opts: &PlayOptions) -> Result<(), Box<dyn Error>> {
+    let layout = Layout::from_annotations(&annotations(cli)?)
+        .map_err(|e| format!("{}: {e}", annotations_path(cli).display()))?;
+    let machine = play(cli, opts)?;
+    print!("{}", layout.decode(machine.mem())?);
+    Ok(())
+}
+
+fn codes(transcript: Option<&Path>, hashes: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let output = match transcript {
+        Some(path) => std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?,
+        None => {
+            let mut output = Vec::new();
+            std::io::stdin().read_to_end(&mut output)?;
+            output
+        }
+    };
+    // codes.txt is optional, but an explicitly given file has to exist.
+    let path = hashes.unwrap_or(Path::new("codes.txt"));
+    let hashes: Option<Hashes> = match std::fs::read_to_string(path) {
+        Ok(text) => Some(
+            text.parse()
+                .map_err(|e| format!("{}: {e}", path.display()))?,
+        ),
+        Err(_) if hashes.is_none() => None,
+        Err(e) => return Err(format!("{}: {e}", path.display()).into()),
+    };
+    for found in codes::find(&String::from_utf8_lossy(&output)) {
+        let mut line = format!("line {:<6} {}", found.line, found.code);
+        if let Some(mirrored) = &found.mirrored {
+            line += &format!("  in a mirror, reads {mirrored}");
+        }
+        if let Some(hashes) = &hashes {
+            line += match hashes.check(&found) {
+                Some(code) if code == found.code => "  valid",
+                Some(_) => "  valid mirrored",
+                None => "  not valid",
+            };
+        }
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Searches for the r7 values that make the teleporter check, A(4, 1), come out as 6. 0
+/// leaves the check off, so it isn't a candidate.
+fn calc_reg_8() {
+    let found = ackermann::search(4, 1, 6, MOD as u32, 1..MOD as u32);
+    if found.is_empty() {
+        println!("NOTHING FOUND");
+    }
+    for r7 in found {
+        println!("FOUND r7 = {r7}");
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::from(2);
+        }
+    };
+    let result = match &cli.command {
+        Command::Run(opts) => run(&cli, opts),
+        Command::Decompile {
+            high_level,
+            options,
+        } => decompile(&cli, *high_level, options),
+        Command::Annotate(action) => annotate(&cli, action),
+        Command::Codes { transcript, hashes } => codes(transcript.as_deref(), hashes.as_deref()),
+        Command::Reg8 => {
+            calc_reg_8();
+            Ok(())
+        }
+        Command::Info => info(&cli),
+        Command::Functions { export } => functions(&cli, *export),
+        Command::Scan { pattern, label } => scan(&cli, pattern.as_deref(), *label),
+        Command::Xref { target, boot } => xref(&cli, target, *boot),
+        Command::Explore { play, format } => explore(&cli, play, *format),
+        Command::Route { play, from, to } => route(&cli, play, from, to),
+        Command::Solve { puzzle, play, grid } => solve(&cli, *puzzle, play, grid.as_deref()),
+        Command::State { play } => state(&cli, play),
+        Command::Help(usage) => {
+            println!("{usage}");
+            Ok(())
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
     }
 }
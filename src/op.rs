@@ -1,6 +1,6 @@
 use crate::error::Error;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Reg(u8);
 
 impl Reg {
@@ -42,13 +42,31 @@ impl TryFrom<u16> for Reg {
     }
 }
 
+/// `r0` to `r7`.
+impl std::str::FromStr for Reg {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.strip_prefix('r')
+            .and_then(|n| n.parse::<u8>().ok())
+            .and_then(|n| Reg::try_from(n).ok())
+            .ok_or(Error::ParseReg)
+    }
+}
+
+impl From<Reg> for u16 {
+    fn from(reg: Reg) -> Self {
+        32768 + reg.0 as u16
+    }
+}
+
 impl std::fmt::Display for Reg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "r{}", self.0)
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Val {
     Literal(u16),
     Reg(Reg),
@@ -79,6 +97,15 @@ impl TryFrom<u16> for Val {
     }
 }
 
+impl From<Val> for u16 {
+    fn from(val: Val) -> Self {
+        match val {
+            Val::Literal(x) => x,
+            Val::Reg(reg) => reg.into(),
+        }
+    }
+}
+
 impl std::fmt::Display for Val {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -88,6 +115,7 @@ impl std::fmt::Display for Val {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Op {
     /// [0] `halt` :: Stop execution and terminate the program
     Halt,
@@ -157,34 +185,114 @@ impl Op {
     }
 }
 
+impl Op {
+    pub fn opcode(&self) -> u16 {
+        match self {
+            Op::Halt => 0,
+            Op::Set(_, _) => 1,
+            Op::Push(_) => 2,
+            Op::Pop(_) => 3,
+            Op::Eq(_, _, _) => 4,
+            Op::Gt(_, _, _) => 5,
+            Op::Jmp(_) => 6,
+            Op::Jt(_, _) => 7,
+            Op::Jf(_, _) => 8,
+            Op::Add(_, _, _) => 9,
+            Op::Mult(_, _, _) => 10,
+            Op::Mod(_, _, _) => 11,
+            Op::And(_, _, _) => 12,
+            Op::Or(_, _, _) => 13,
+            Op::Not(_, _) => 14,
+            Op::Rmem(_, _) => 15,
+            Op::Wmem(_, _) => 16,
+            Op::Call(_) => 17,
+            Op::Ret => 18,
+            Op::Out(_) => 19,
+            Op::In(_) => 20,
+            Op::Noop => 21,
+        }
+    }
+
+    /// Appends the binary form of the op, the inverse of `Op::try_from(&[u16])`.
+    pub fn encode(&self, out: &mut Vec<u16>) {
+        out.push(self.opcode());
+        match *self {
+            Op::Halt | Op::Ret | Op::Noop => {}
+            Op::Push(a) | Op::Jmp(a) | Op::Call(a) | Op::Out(a) => out.push(a.into()),
+            Op::Pop(a) | Op::In(a) => out.push(a.into()),
+            Op::Set(a, b) | Op::Not(a, b) | Op::Rmem(a, b) => out.extend([a.into(), u16::from(b)]),
+            Op::Jt(a, b) | Op::Jf(a, b) | Op::Wmem(a, b) => out.extend([u16::from(a), b.into()]),
+            Op::Eq(a, b, c)
+            | Op::Gt(a, b, c)
+            | Op::Add(a, b, c)
+            | Op::Mult(a, b, c)
+            | Op::Mod(a, b, c)
+            | Op::And(a, b, c)
+            | Op::Or(a, b, c) => out.extend([a.into(), u16::from(b), c.into()]),
+        }
+    }
+}
+
 impl TryFrom<&[u16]> for Op {
     type Error = Error;
 
     fn try_from(s: &[u16]) -> Result<Self, Self::Error> {
-        let op = match s[0] {
+        let Some(&opcode) = s.first() else {
+            return Err(Error::Truncated);
+        };
+        let arg = |i: usize| s.get(i).copied().ok_or(Error::Truncated);
+        let op = match opcode {
             0 => Op::Halt,
-            1 => Op::Set(s[1].try_into()?, s[2].try_into()?),
-            2 => Op::Push(s[1].try_into()?),
-            3 => Op::Pop(s[1].try_into()?),
-            4 => Op::Eq(s[1].try_into()?, s[2].try_into()?, s[3].try_into()?),
-            5 => Op::Gt(s[1].try_into()?, s[2].try_into()?, s[3].try_into()?),
-            6 => Op::Jmp(s[1].try_into()?),
-            7 => Op::Jt(s[1].try_into()?, s[2].try_into()?),
-            8 => Op::Jf(s[1].try_into()?, s[2].try_into()?),
-            9 => Op::Add(s[1].try_into()?, s[2].try_into()?, s[3].try_into()?),
-            10 => Op::Mult(s[1].try_into()?, s[2].try_into()?, s[3].try_into()?),
-            11 => Op::Mod(s[1].try_into()?, s[2].try_into()?, s[3].try_into()?),
-            12 => Op::And(s[1].try_into()?, s[2].try_into()?, s[3].try_into()?),
-            13 => Op::Or(s[1].try_into()?, s[2].try_into()?, s[3].try_into()?),
-            14 => Op::Not(s[1].try_into()?, s[2].try_into()?),
-            15 => Op::Rmem(s[1].try_into()?, s[2].try_into()?),
-            16 => Op::Wmem(s[1].try_into()?, s[2].try_into()?),
-            17 => Op::Call(s[1].try_into()?),
+            1 => Op::Set(arg(1)?.try_into()?, arg(2)?.try_into()?),
+            2 => Op::Push(arg(1)?.try_into()?),
+            3 => Op::Pop(arg(1)?.try_into()?),
+            4 => Op::Eq(
+                arg(1)?.try_into()?,
+                arg(2)?.try_into()?,
+                arg(3)?.try_into()?,
+            ),
+            5 => Op::Gt(
+                arg(1)?.try_into()?,
+                arg(2)?.try_into()?,
+                arg(3)?.try_into()?,
+            ),
+            6 => Op::Jmp(arg(1)?.try_into()?),
+            7 => Op::Jt(arg(1)?.try_into()?, arg(2)?.try_into()?),
+            8 => Op::Jf(arg(1)?.try_into()?, arg(2)?.try_into()?),
+            9 => Op::Add(
+                arg(1)?.try_into()?,
+                arg(2)?.try_into()?,
+                arg(3)?.try_into()?,
+            ),
+            10 => Op::Mult(
+                arg(1)?.try_into()?,
+                arg(2)?.try_into()?,
+                arg(3)?.try_into()?,
+            ),
+            11 => Op::Mod(
+                arg(1)?.try_into()?,
+                arg(2)?.try_into()?,
+                arg(3)?.try_into()?,
+            ),
+            12 => Op::And(
+                arg(1)?.try_into()?,
+                arg(2)?.try_into()?,
+                arg(3)?.try_into()?,
+            ),
+            13 => Op::Or(
+                arg(1)?.try_into()?,
+                arg(2)?.try_into()?,
+                arg(3)?.try_into()?,
+            ),
+            14 => Op::Not(arg(1)?.try_into()?, arg(2)?.try_into()?),
+            15 => Op::Rmem(arg(1)?.try_into()?, arg(2)?.try_into()?),
+            16 => Op::Wmem(arg(1)?.try_into()?, arg(2)?.try_into()?),
+            17 => Op::Call(arg(1)?.try_into()?),
             18 => Op::Ret,
-            19 => Op::Out(s[1].try_into()?),
-            20 => Op::In(s[1].try_into()?),
+            19 => Op::Out(arg(1)?.try_into()?),
+            20 => Op::In(arg(1)?.try_into()?),
             21 => Op::Noop,
-            _ => return Err(Error::ParseOp(s[0])),
+            _ => return Err(Error::ParseOp(opcode)),
         };
         Ok(op)
     }
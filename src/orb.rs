@@ -0,0 +1,324 @@
+//! The vault lock: a grid of rooms with numbers and operators on their floors. The orb starts
+//! out weighing the number at the antechamber, and each operator room followed by a number
+//! room applies that operation to its weight. It has to weigh the number on the vault door
+//! when it gets there.
+//!
+//! The orb shatters if its weight leaves 0..=32767, goes back to its pedestal if it's carried
+//! into the antechamber, and evaporates if it reaches the door with the wrong weight.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    explore::parse_room,
+    machine::{Buffer, Machine},
+};
+
+const MAX_WEIGHT: i64 = 32767;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cell {
+    Number(i64),
+    /// `+`, `-` or `*`.
+    Op(char),
+}
+
+impl std::str::FromStr for Cell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "+" | "-" | "*" => Ok(Cell::Op(s.chars().next().unwrap())),
+            _ => s
+                .parse()
+                .map(Cell::Number)
+                .map_err(|_| format!("expected a number or one of + - *, got {s:?}")),
+        }
+    }
+}
+
+type Pos = (usize, usize);
+
+/// A room and the orb's weight in it.
+type State = (Pos, i64);
+
+/// Directions and the change in (row, column) they make, north being up.
+const DIRECTIONS: [(&str, isize, isize); 4] = [
+    ("north", -1, 0),
+    ("east", 0, 1),
+    ("south", 1, 0),
+    ("west", 0, -1),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grid {
+    /// Rows from north to south. `None` where there is no room.
+    pub cells: Vec<Vec<Option<Cell>>>,
+    /// The antechamber, where the orb is picked up.
+    pub start: Pos,
+    /// The vault door.
+    pub end: Pos,
+    /// What the orb has to weigh at the door.
+    pub target: i64,
+}
+
+/// A grid file has a row of cells per line, as seen from above with north up, and then a
+/// `= <target>` line. The antechamber is the bottom-left cell and the door the top-right one:
+///
+/// ```text
+///  *  8  -  1
+///  4  * 11  *
+///  +  4  - 18
+/// 22  -  9  *
+/// = 30
+/// ```
+impl std::str::FromStr for Grid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cells = Vec::new();
+        let mut target = None;
+        for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            if let Some(t) = line.strip_prefix('=') {
+                let t = t.trim();
+                target = Some(
+                    t.parse()
+                        .map_err(|e| format!("invalid target {t:?}: {e}"))?,
+                );
+                continue;
+            }
+            let row = line
+                .split_whitespace()
+                .map(|c| c.parse().map(Some))
+                .collect::<Result<Vec<_>, _>>()?;
+            cells.push(row);
+        }
+        let target = target.ok_or("missing the \"= <target>\" line")?;
+        let (Some(first), Some(last)) = (cells.first(), cells.last()) else {
+            return Err("no rows".to_owned());
+        };
+        if !matches!(last[0], Some(Cell::Number(_))) {
+            return Err("the bottom-left cell has to be a number".to_owned());
+        }
+        let end = (0, first.len() - 1);
+        Ok(Grid {
+            start: (cells.len() - 1, 0),
+            end,
+            cells,
+            target,
+        })
+    }
+}
+
+impl Grid {
+    fn cell(&self, (row, col): Pos) -> Option<Cell> {
+        *self.cells.get(row)?.get(col)?
+    }
+
+    fn neighbors(&self, (row, col): Pos) -> impl Iterator<Item = (&'static str, Pos)> + '_ {
+        DIRECTIONS.iter().filter_map(move |&(dir, dr, dc)| {
+            let pos = (row.checked_add_signed(dr)?, col.checked_add_signed(dc)?);
+            self.cell(pos).map(|_| (dir, pos))
+        })
+    }
+}
+
+/// A step of the solution: the direction to go, and the orb's weight after it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub dir: &'static str,
+    pub weight: i64,
+}
+
+/// The fewest moves from the antechamber that get the orb to the door at the right weight,
+/// found by breadth-first search over (room, weight).
+pub fn solve(grid: &Grid) -> Option<Vec<Step>> {
+    let Some(Cell::Number(start)) = grid.cell(grid.start) else {
+        return None;
+    };
+    let mut prev: HashMap<State, Option<(State, &str)>> =
+        HashMap::from([((grid.start, start), None)]);
+    let mut queue = VecDeque::from([(grid.start, start)]);
+    while let Some((pos, weight)) = queue.pop_front() {
+        if pos == grid.end {
+            let mut steps = Vec::new();
+            let mut at = (pos, weight);
+            while let Some(Some((before, dir))) = prev.get(&at) {
+                steps.push(Step { dir, weight: at.1 });
+                at = *before;
+            }
+            steps.reverse();
+            return Some(steps);
+        }
+        for (dir, next) in grid.neighbors(pos) {
+            if next == grid.start {
+                continue;
+            }
+            let next_weight = match (grid.cell(pos), grid.cell(next)) {
+                (Some(Cell::Number(_)), Some(Cell::Op(_))) => weight,
+                (Some(Cell::Op(op)), Some(Cell::Number(n))) => match op {
+                    '+' => weight + n,
+                    '-' => weight - n,
+                    _ => weight * n,
+                },
+                _ => continue,
+            };
+            let state = (next, next_weight);
+            if !(0..=MAX_WEIGHT).contains(&next_weight)
+                || next == grid.end && next_weight != grid.target
+                || prev.contains_key(&state)
+            {
+                continue;
+            }
+            prev.insert(state, Some(((pos, weight), dir)));
+            queue.push_back(state);
+        }
+    }
+    None
+}
+
+/// What's quoted in `text` right after `before`, e.g. 22 from "the number '22' is" with
+/// `before` being "number '".
+fn quoted_after(text: &str, before: &str) -> Option<String> {
+    let (_, rest) = text.split_once(before)?;
+    let (quoted, _) = rest.split_once('\'')?;
+    Some(quoted.to_owned())
+}
+
+/// Maps the vault lock by walking it, starting from the antechamber, where the machine must be
+/// waiting for input. The machine is left wherever the last room tried put it.
+pub fn read(machine: &mut Machine<Buffer>) -> Result<Grid, String> {
+    let room = parse_room(&machine.command("look").map_err(|e| format!("look: {e}"))?)
+        .ok_or("`look` didn't describe a room")?;
+    let start = quoted_after(&room.description, "number '")
+        .filter(|_| room.description.contains("pedestal"))
+        .ok_or("not in the vault antechamber")?
+        .parse::<Cell>()?;
+    // Rooms relative to the antechamber, which is at (0, 0) until the grid is known.
+    let mut cells = HashMap::from([((0, 0), start)]);
+    let mut end = None;
+    let mut queue = VecDeque::from([((0isize, 0isize), machine.snapshot(), room)]);
+    while let Some(((row, col), snapshot, room)) = queue.pop_front() {
+        for &(dir, dr, dc) in &DIRECTIONS {
+            let pos = (row + dr, col + dc);
+            if !room.exits.iter().any(|e| e.name == dir) || cells.contains_key(&pos) {
+                continue;
+            }
+            machine.restore(&snapshot);
+            let Some(next) = machine
+                .command(&format!("go {dir}"))
+                .ok()
+                .and_then(|o| parse_room(&o))
+            else {
+                continue;
+            };
+            let cell = quoted_after(&next.description, "depicting the number '")
+                .or_else(|| quoted_after(&next.description, "depicting a '"));
+            let Some(cell) = cell else {
+                continue;
+            };
+            cells.insert(pos, cell.parse()?);
+            if let Some(target) = quoted_after(&next.description, "it has a large '") {
+                let target = target
+                    .parse()
+                    .map_err(|e| format!("invalid number on the door {target:?}: {e}"))?;
+                end = Some((pos, target));
+            }
+            queue.push_back((pos, machine.snapshot(), next));
+        }
+    }
+    let (end, target) = end.ok_or("didn't find the vault door")?;
+    let top = cells.keys().map(|p| p.0).min().unwrap();
+    let left = cells.keys().map(|p| p.1).min().unwrap();
+    let rows = (cells.keys().map(|p| p.0).max().unwrap() - top + 1) as usize;
+    let cols = (cells.keys().map(|p| p.1).max().unwrap() - left + 1) as usize;
+    let at = |(row, col): (isize, isize)| ((row - top) as usize, (col - left) as usize);
+    let mut grid = vec![vec![None; cols]; rows];
+    for (pos, cell) in cells {
+        let (row, col) = at(pos);
+        grid[row][col] = Some(cell);
+    }
+    Ok(Grid {
+        cells: grid,
+        start: at((0, 0)),
+        end: at(end),
+        target,
+    })
+}
+
+/// `steps` as input for [`crate::machine::Machine::set_script`], with comments following the
+/// orb's weight.
+pub fn script(grid: &Grid, steps: &[Step]) -> String {
+    let start = match grid.cell(grid.start) {
+        Some(Cell::Number(n)) => n,
+        _ => 0,
+    };
+    let mut out = format!(
+        "// the orb weighs {start} and has to weigh {} at the door\n",
+        grid.target
+    );
+    let mut weight = start;
+    for step in steps {
+        out += &format!("go {}\n", step.dir);
+        if step.weight != weight {
+            out += &format!("// the orb weighs {}\n", step.weight);
+            weight = step.weight;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{error::Error, machine::load_rom, rom};
+
+    const VAULT: &str = "
+         *  8  -  1
+         4  * 11  *
+         +  4  - 18
+        22  -  9  *
+        = 30
+    ";
+
+    #[test]
+    fn finds_shortest_path() {
+        let grid: Grid = VAULT.parse().unwrap();
+        assert_eq!(grid.start, (3, 0));
+        assert_eq!(grid.end, (0, 3));
+        let steps = solve(&grid).unwrap();
+        let dirs: Vec<_> = steps.iter().map(|s| s.dir).collect();
+        assert_eq!(
+            dirs,
+            [
+                "north", "east", "east", "north", "west", "south", "east", "east", "west", "north",
+                "north", "east"
+            ]
+        );
+        assert_eq!(steps.last().unwrap().weight, 30);
+        assert!(script(&grid, &steps).starts_with("// the orb weighs 22 and has to weigh 30"));
+    }
+
+    #[test]
+    fn rejects_bad_grids() {
+        assert!("1 + 2".parse::<Grid>().is_err());
+        assert!("1 / 2\n= 3".parse::<Grid>().is_err());
+        assert!("+ 2\n= 3".parse::<Grid>().is_err());
+        let unsolvable: Grid = "* 1\n2 +\n= 30".parse().unwrap();
+        assert_eq!(solve(&unsolvable), None);
+    }
+
+    #[test]
+    fn reads_the_vault_from_the_game() {
+        let mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let profile = rom::detect(&mem);
+        let mut machine = Machine::with_console(mem, Buffer::default());
+        machine.hack_teleporter(profile.teleporter_call.unwrap(), profile.r7.unwrap());
+        let script = std::fs::read_to_string("script.txt").unwrap();
+        let end = script.find("take orb").unwrap();
+        machine.set_script(script[..end].parse().unwrap());
+        assert!(matches!(machine.run(), Err(Error::InputExhausted(_))));
+        assert_eq!(read(&mut machine).unwrap(), VAULT.parse().unwrap());
+    }
+}
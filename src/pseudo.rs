@@ -0,0 +1,486 @@
+//! Pseudo-C for `decompile --high-level`. Each function's ops are laid out in address order
+//! and branches are matched against the shapes that code takes for if/else and loops; anything
+//! else is left as a `goto`.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+};
+
+use crate::{
+    annotations::Annotations,
+    functions::{Function, Functions, Regs, next_addr, reg_bit, regs},
+    op::{Op, Reg, Val},
+};
+
+const INDENT: &str = "    ";
+
+/// Prints every function found in `mem` as pseudo-C.
+pub fn decompile(mem: &[u16], annotations: &Annotations, out: &mut impl Write) -> std::fmt::Result {
+    let funcs = Functions::analyze(mem, annotations);
+    for f in funcs.functions.values() {
+        let sig = annotations
+            .functions
+            .get(&f.entry)
+            .and_then(|a| a.signature.clone())
+            .unwrap_or_else(|| f.signature());
+        writeln!(out, "// 0x{:04x}", f.entry)?;
+        writeln!(out, "fn {}{sig} {{", f.name)?;
+        if f.clobbers() != 0 {
+            writeln!(out, "{INDENT}// clobbers {}", list(f.clobbers()))?;
+        }
+        let mut w = Writer::new(&funcs, annotations, f);
+        w.emit(0, f.body.len(), None, None, 1);
+        for line in w.finish() {
+            writeln!(out, "{line}")?;
+        }
+        writeln!(out, "}}\n")?;
+    }
+    Ok(())
+}
+
+fn list(r: Regs) -> String {
+    regs(r)
+        .map(|r| r.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// What a conditional jump tests.
+#[derive(Clone, Copy)]
+enum Cond {
+    Test(Val),
+    Eq(Val, Val),
+    Gt(Val, Val),
+}
+
+/// A conditional jump, with the `eq` or `gt` that computes its condition folded in.
+#[derive(Clone, Copy)]
+struct Branch {
+    cond: Cond,
+    /// Whether the jump is taken when `cond` holds (`jt`) or doesn't (`jf`).
+    taken_when: bool,
+    target: u16,
+    /// Index of the jump in the body.
+    jump: usize,
+}
+
+/// The header and exit of the innermost loop, for `continue` and `break`.
+type LoopCtx = Option<(u16, u16)>;
+
+struct Writer<'a> {
+    funcs: &'a Functions,
+    annotations: &'a Annotations,
+    f: &'a Function,
+    index: HashMap<u16, usize>,
+    /// Indices of the jumps to each address from later in the body.
+    back_jumps: HashMap<u16, Vec<usize>>,
+    targets: BTreeSet<u16>,
+    lines: Vec<(Option<u16>, String)>,
+    gotos: BTreeSet<u16>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(funcs: &'a Functions, annotations: &'a Annotations, f: &'a Function) -> Self {
+        let index: HashMap<u16, usize> = f.body.iter().enumerate().map(|(i, &a)| (a, i)).collect();
+        let mut back_jumps: HashMap<u16, Vec<usize>> = HashMap::new();
+        let mut targets = BTreeSet::new();
+        for (i, addr) in f.body.iter().enumerate() {
+            if let Op::Jmp(Val::Literal(to))
+            | Op::Jt(_, Val::Literal(to))
+            | Op::Jf(_, Val::Literal(to)) = funcs.ops[addr]
+            {
+                targets.insert(to);
+                if to <= *addr {
+                    back_jumps.entry(to).or_default().push(i);
+                }
+            }
+        }
+        Writer {
+            funcs,
+            annotations,
+            f,
+            index,
+            back_jumps,
+            targets,
+            lines: Vec::new(),
+            gotos: BTreeSet::new(),
+        }
+    }
+
+    fn op(&self, i: usize) -> Op {
+        self.funcs.ops[&self.f.body[i]]
+    }
+
+    fn line(&mut self, addr: Option<u16>, depth: usize, text: String) {
+        self.lines
+            .push((addr, format!("{}{text}", INDENT.repeat(depth))));
+    }
+
+    /// Lines with labels for the `goto` targets put in front of the ops they name.
+    fn finish(self) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut gotos = self.gotos.iter().peekable();
+        for (addr, text) in &self.lines {
+            while let (Some(a), Some(&&g)) = (*addr, gotos.peek())
+                && g <= a
+            {
+                out.push(format!("{}:", self.name(g)));
+                gotos.next();
+            }
+            out.push(text.clone());
+        }
+        out
+    }
+
+    fn name(&self, addr: u16) -> String {
+        match self.annotations.label(addr).or(self.funcs.name(addr)) {
+            Some(name) => name.to_owned(),
+            None => format!("loc_{addr:04x}"),
+        }
+    }
+
+    fn val(&self, v: Val) -> String {
+        match v {
+            Val::Reg(r) => r.to_string(),
+            Val::Literal(x) if x < 0x100 => x.to_string(),
+            Val::Literal(x) => format!("0x{x:04x}"),
+        }
+    }
+
+    fn cond(&self, cond: Cond, holds: bool) -> String {
+        match (cond, holds) {
+            (Cond::Test(v), true) => self.val(v),
+            (Cond::Test(v), false) => format!("!{}", self.val(v)),
+            (Cond::Eq(a, b), true) => format!("{} == {}", self.val(a), self.val(b)),
+            (Cond::Eq(a, b), false) => format!("{} != {}", self.val(a), self.val(b)),
+            (Cond::Gt(a, b), true) => format!("{} > {}", self.val(a), self.val(b)),
+            (Cond::Gt(a, b), false) => format!("{} <= {}", self.val(a), self.val(b)),
+        }
+    }
+
+    /// The branch starting at index `i`, if any. An `eq` or `gt` is folded into the jump
+    /// after it when the jump tests its result and nothing else reads it.
+    fn branch_at(&self, i: usize) -> Option<Branch> {
+        let jump = |op: Op| match op {
+            Op::Jt(a, Val::Literal(to)) => Some((a, true, to)),
+            Op::Jf(a, Val::Literal(to)) => Some((a, false, to)),
+            _ => None,
+        };
+        let (cond, dest) = match self.op(i) {
+            Op::Eq(a, b, c) => (Cond::Eq(b, c), a),
+            Op::Gt(a, b, c) => (Cond::Gt(b, c), a),
+            op => {
+                let (a, taken_when, target) = jump(op)?;
+                return Some(Branch {
+                    cond: Cond::Test(a),
+                    taken_when,
+                    target,
+                    jump: i,
+                });
+            }
+        };
+        let next = *self.f.body.get(i + 1)?;
+        let (a, taken_when, target) = jump(self.op(i + 1))?;
+        let foldable = next == next_addr(self.f.body[i], &self.op(i))
+            && a == Val::Reg(dest)
+            && !self.targets.contains(&next)
+            && self.f.live_out[&next] & reg_bit(dest) == 0;
+        foldable.then_some(Branch {
+            cond,
+            taken_when,
+            target,
+            jump: i + 1,
+        })
+    }
+
+    fn jump_to(&mut self, to: u16, ctx: LoopCtx) -> String {
+        match ctx {
+            Some((header, _)) if header == to => "continue".to_owned(),
+            Some((_, exit)) if exit == to => "break".to_owned(),
+            _ => {
+                self.gotos.insert(to);
+                format!("goto {}", self.name(to))
+            }
+        }
+    }
+
+    /// Writes the ops at indices `lo..hi`. `skip_loop` is the index of a loop header whose
+    /// loop is already being written.
+    fn emit(&mut self, lo: usize, hi: usize, ctx: LoopCtx, skip_loop: Option<usize>, depth: usize) {
+        let mut i = lo;
+        while i < hi {
+            let addr = self.f.body[i];
+            let back = self
+                .back_jumps
+                .get(&addr)
+                .and_then(|js| js.iter().copied().filter(|&j| j >= i && j < hi).max());
+            if skip_loop != Some(i)
+                && let Some(j) = back
+            {
+                i = self.emit_loop(i, j, depth);
+                continue;
+            }
+            if let Some(b) = self.branch_at(i) {
+                i = self.emit_branch(i, hi, b, ctx, depth);
+                continue;
+            }
+            match self.op(i) {
+                Op::Jmp(Val::Literal(to)) => {
+                    let last = i + 1 == hi;
+                    if !(last && self.f.body.get(hi) == Some(&to)) {
+                        let text = self.jump_to(to, ctx);
+                        self.line(Some(addr), depth, format!("{text};"));
+                    }
+                }
+                op => {
+                    if let Some(text) = self.statement(op) {
+                        self.line(Some(addr), depth, text);
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+
+    /// Writes the loop from index `i` to the jump back to it at index `j`. Returns the index
+    /// to carry on from.
+    fn emit_loop(&mut self, i: usize, j: usize, depth: usize) -> usize {
+        let addr = self.f.body[i];
+        let exit = next_addr(self.f.body[j], &self.op(j));
+        let ctx = Some((addr, exit));
+        if let Op::Jmp(_) = self.op(j) {
+            if let Some(b) = self.branch_at(i)
+                && b.target == exit
+                && b.jump < j
+            {
+                let cond = self.cond(b.cond, !b.taken_when);
+                self.line(Some(addr), depth, format!("while ({cond}) {{"));
+                self.emit(b.jump + 1, j, ctx, None, depth + 1);
+            } else {
+                self.line(Some(addr), depth, "while (1) {".to_owned());
+                self.emit(i, j, ctx, Some(i), depth + 1);
+            }
+            self.line(None, depth, "}".to_owned());
+            return j + 1;
+        }
+        let folded = (j > i)
+            .then(|| self.branch_at(j - 1))
+            .flatten()
+            .filter(|b| b.jump == j);
+        let (b, body_end) = match folded {
+            Some(b) => (b, j - 1),
+            None => (self.branch_at(j).expect("back jumps are jumps"), j),
+        };
+        self.line(Some(addr), depth, "do {".to_owned());
+        self.emit(i, body_end, ctx, Some(i), depth + 1);
+        let cond = self.cond(b.cond, b.taken_when);
+        self.line(None, depth, format!("}} while ({cond});"));
+        j + 1
+    }
+
+    /// Writes a forward branch as if or if/else when its targets allow, or as a conditional
+    /// jump otherwise. Returns the index to carry on from.
+    fn emit_branch(&mut self, i: usize, hi: usize, b: Branch, ctx: LoopCtx, depth: usize) -> usize {
+        let addr = self.f.body[i];
+        let jump_addr = self.f.body[b.jump];
+        let target = self
+            .index
+            .get(&b.target)
+            .copied()
+            .filter(|&t| b.target > jump_addr && t <= hi);
+        let Some(t) = target else {
+            let cond = self.cond(b.cond, b.taken_when);
+            let text = self.jump_to(b.target, ctx);
+            self.line(Some(addr), depth, format!("if ({cond}) {text};"));
+            return b.jump + 1;
+        };
+        let cond = self.cond(b.cond, !b.taken_when);
+        self.line(Some(addr), depth, format!("if ({cond}) {{"));
+        let else_end = (t > b.jump + 1)
+            .then(|| match self.op(t - 1) {
+                Op::Jmp(Val::Literal(end)) if end > b.target => {
+                    self.index.get(&end).copied().filter(|&e| e <= hi)
+                }
+                _ => None,
+            })
+            .flatten();
+        match else_end {
+            Some(e) => {
+                self.emit(b.jump + 1, t - 1, ctx, None, depth + 1);
+                self.line(Some(b.target), depth, "} else {".to_owned());
+                self.emit(t, e, ctx, None, depth + 1);
+                self.line(None, depth, "}".to_owned());
+                e
+            }
+            None => {
+                self.emit(b.jump + 1, t, ctx, None, depth + 1);
+                self.line(None, depth, "}".to_owned());
+                t
+            }
+        }
+    }
+
+    fn call(&self, to: Val) -> String {
+        let Val::Literal(to) = to else {
+            return format!("(*{})();", self.val(to));
+        };
+        let name = self.name(to);
+        let Some(callee) = self.funcs.functions.get(&to) else {
+            return format!("{name}();");
+        };
+        let args = list(callee.args);
+        match callee.returns.count_ones() {
+            0 => format!("{name}({args});"),
+            1 => format!("{} = {name}({args});", list(callee.returns)),
+            _ => format!("({}) = {name}({args});", list(callee.returns)),
+        }
+    }
+
+    fn mem(&self, at: Val) -> String {
+        match at {
+            Val::Literal(a) => match self.annotations.variables.get(&a) {
+                Some(var) => var.name.clone(),
+                None => format!("mem[0x{a:04x}]"),
+            },
+            Val::Reg(r) => format!("mem[{r}]"),
+        }
+    }
+
+    fn binary(&self, a: Reg, b: Val, sym: &str, c: Val) -> String {
+        if b == Val::Reg(a) {
+            format!("{a} {sym}= {};", self.val(c))
+        } else {
+            format!("{a} = {} {sym} {};", self.val(b), self.val(c))
+        }
+    }
+
+    fn statement(&self, op: Op) -> Option<String> {
+        Some(match op {
+            Op::Halt => "halt();".to_owned(),
+            Op::Set(a, b) => format!("{a} = {};", self.val(b)),
+            Op::Push(a) => format!("push({});", self.val(a)),
+            Op::Pop(a) => format!("{a} = pop();"),
+            Op::Eq(a, b, c) => format!("{a} = {} == {};", self.val(b), self.val(c)),
+            Op::Gt(a, b, c) => format!("{a} = {} > {};", self.val(b), self.val(c)),
+            Op::Add(a, b, Val::Literal(0x7fff)) => match b == Val::Reg(a) {
+                true => format!("{a} -= 1;"),
+                false => format!("{a} = {} - 1;", self.val(b)),
+            },
+            Op::Add(a, b, c) => self.binary(a, b, "+", c),
+            Op::Mult(a, b, c) => self.binary(a, b, "*", c),
+            Op::Mod(a, b, c) => self.binary(a, b, "%", c),
+            Op::And(a, b, c) => self.binary(a, b, "&", c),
+            Op::Or(a, b, c) => self.binary(a, b, "|", c),
+            Op::Not(a, b) => format!("{a} = ~{};", self.val(b)),
+            Op::Rmem(a, b) => format!("{a} = {};", self.mem(b)),
+            Op::Wmem(a, b) => format!("{} = {};", self.mem(a), self.val(b)),
+            Op::Call(to) => self.call(to),
+            Op::Ret => match self.f.returns.count_ones() {
+                0 => "return;".to_owned(),
+                1 => format!("return {};", list(self.f.returns)),
+                _ => format!("return ({});", list(self.f.returns)),
+            },
+            // Surrogates aren't characters, so they're shown as numbers.
+            Op::Out(Val::Literal(c)) => char::from_u32(c as u32)
+                .map_or_else(|| format!("out({c});"), |c| format!("out({c:?});")),
+            Op::Out(a) => format!("out({});", self.val(a)),
+            Op::In(a) => format!("{a} = in();"),
+            Op::Jmp(a) => format!("goto *{};", self.val(a)),
+            Op::Jt(a, b) => format!("if ({}) goto *{};", self.val(a), self.val(b)),
+            Op::Jf(a, b) => format!("if (!{}) goto *{};", self.val(a), self.val(b)),
+            Op::Noop => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op::Reg;
+
+    const R0: Reg = Reg::REG0;
+    const R1: Reg = Reg::REG1;
+
+    fn assemble(ops: &[Op]) -> Vec<u16> {
+        let mut mem = Vec::new();
+        for op in ops {
+            op.encode(&mut mem);
+        }
+        mem
+    }
+
+    fn pseudo(ops: &[Op]) -> String {
+        let mut out = String::new();
+        decompile(&assemble(ops), &Annotations::default(), &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn if_else_with_folded_condition() {
+        let out = pseudo(&[
+            Op::Call(Val::Literal(3)),
+            Op::Halt,
+            Op::Eq(R1, Val::Reg(R0), Val::Literal(3)),
+            Op::Jf(Val::Reg(R1), Val::Literal(15)),
+            Op::Set(R0, Val::Literal(1)),
+            Op::Jmp(Val::Literal(18)),
+            Op::Set(R0, Val::Literal(2)),
+            Op::Out(Val::Reg(R0)),
+            Op::Ret,
+        ]);
+        let want = "\
+// 0x0003
+fn sub_0003(r0) {
+    // clobbers r0, r1
+    if (r0 == 3) {
+        r0 = 1;
+    } else {
+        r0 = 2;
+    }
+    out(r0);
+    return;
+}
+";
+        assert!(out.contains(want), "{out}");
+    }
+
+    #[test]
+    fn while_loop_with_decrement() {
+        let out = pseudo(&[
+            Op::Jf(Val::Reg(R0), Val::Literal(11)),
+            Op::Out(Val::Literal(b'x' as u16)),
+            Op::Add(R0, Val::Reg(R0), Val::Literal(0x7fff)),
+            Op::Jmp(Val::Literal(0)),
+            Op::Halt,
+        ]);
+        let want = "\
+fn main(r0) {
+    // clobbers r0
+    while (r0) {
+        out('x');
+        r0 -= 1;
+    }
+    halt();
+}
+";
+        assert!(out.contains(want), "{out}");
+    }
+
+    #[test]
+    fn calls_show_arguments_and_results() {
+        let out = pseudo(&[
+            Op::Set(R0, Val::Literal(2)),
+            Op::Call(Val::Literal(8)),
+            Op::Out(Val::Reg(R0)),
+            Op::Halt,
+            Op::Add(R0, Val::Reg(R0), Val::Literal(1)),
+            Op::Ret,
+        ]);
+        assert!(out.contains("    r0 = sub_0008(r0);\n"), "{out}");
+        assert!(
+            out.contains("fn sub_0008(r0) -> r0 {\n    r0 += 1;\n    return r0;\n}"),
+            "{out}"
+        );
+    }
+}
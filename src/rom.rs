@@ -0,0 +1,99 @@
+//! Telling challenge binaries apart. Every published `challenge.bin` runs the same game, but
+//! codes, addresses and the teleporter's eighth register value differ between them.
+
+use crate::signature;
+
+/// What the tools need to know about a particular ROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    /// The `call recursive_func` in the teleporter check, which the teleporter hack skips.
+    pub teleporter_call: Option<u16>,
+    /// The Ackermann-like function the teleporter check calls.
+    pub recursive_func: Option<u16>,
+    /// Eighth register value that passes the teleporter check, if it has been worked out.
+    pub r7: Option<u16>,
+    /// Word holding the address of the room the player is in, which tells rooms with the
+    /// same text apart.
+    pub current_room: Option<u16>,
+}
+
+struct Known {
+    fingerprint: u64,
+    name: &'static str,
+    teleporter_call: u16,
+    recursive_func: u16,
+    r7: u16,
+    current_room: u16,
+}
+
+/// ROMs that have been played through. See `notes.md` for where the values came from.
+const KNOWN: &[Known] = &[Known {
+    fingerprint: 0xcf7e_3b69_0099_38dd,
+    name: "Aneurysm9/vm_challenge",
+    teleporter_call: 0x1587,
+    recursive_func: 0x17a1,
+    r7: 25734,
+    current_room: 0x0ac2,
+}];
+
+/// FNV-1a over the ROM's words, little-endian, as they are stored on disk.
+pub fn fingerprint(mem: &[u16]) -> u64 {
+    mem.iter()
+        .flat_map(|w| w.to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+}
+
+/// Looks the ROM up by fingerprint, falling back to searching it for the routines the tools
+/// care about. Unknown ROMs never have `r7`; it has to be searched for with `reg8`.
+pub fn detect(mem: &[u16]) -> Profile {
+    let fp = fingerprint(mem);
+    if let Some(known) = KNOWN.iter().find(|k| k.fingerprint == fp) {
+        return Profile {
+            name: known.name.to_owned(),
+            teleporter_call: Some(known.teleporter_call),
+            recursive_func: Some(known.recursive_func),
+            r7: Some(known.r7),
+            current_room: Some(known.current_room),
+        };
+    }
+    let check = signature::find(mem, "teleporter_check");
+    Profile {
+        name: format!("unknown ({fp:016x})"),
+        teleporter_call: check.as_ref().map(|m| m.addr),
+        recursive_func: signature::find(mem, "recursive_func").map(|m| m.addr),
+        r7: None,
+        current_room: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::machine::load_rom;
+
+    #[test]
+    fn detects_known_rom() {
+        let mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let profile = detect(&mem);
+        assert_eq!(profile.name, "Aneurysm9/vm_challenge");
+        assert_eq!(profile.r7, Some(25734));
+    }
+
+    #[test]
+    fn searches_unknown_rom() {
+        let mut mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let known = detect(&mem);
+        // Any change to the ROM makes it unknown, but the routines are still found.
+        mem.push(0);
+        let profile = detect(&mem);
+        assert!(profile.name.starts_with("unknown"));
+        assert_eq!(profile.teleporter_call, known.teleporter_call);
+        assert_eq!(profile.recursive_func, known.recursive_func);
+        assert_eq!(profile.r7, None);
+    }
+}
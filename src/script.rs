@@ -0,0 +1,321 @@
+//! Scripts of pre-programmed input, for [`crate::machine::Machine::set_script`]. Each line is
+//! typed as it is, except for `//` comments, which are skipped, and directives:
+//!
+//! ```text
+//! @expect "<text>"            stop with an error unless the game printed <text> since the
+//!                             last line of input
+//! @setreg <reg> <value>       set a register, e.g. @setreg r7 25734
+//! @setreg r7                  set r7 to the value the teleporter hack knows for the ROM
+//! @patch <addr> <value>...    write values to memory from <addr> on
+//! @snapshot <name>            take a snapshot, for `!load <name>`
+//! @include <file>             play <file>, relative to this script, here
+//! @pause                      hand over to the console until `!resume`
+//! @quiet                      stop showing the game's output and the script's input
+//! @echo                       show them again, as is the default
+//! ```
+//!
+//! Quiet mode also ends when the script does or pauses. Addresses are hex, with or without
+//! `0x`, and other numbers decimal unless prefixed with `0x`.
+
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::op::Reg;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    /// A line of input, without its newline.
+    Input(String),
+    Expect(String),
+    /// `None` for the teleporter hack's r7.
+    SetReg(Reg, Option<u16>),
+    Patch(u16, Vec<u16>),
+    Snapshot(String),
+    Pause,
+    Quiet,
+    Echo,
+}
+
+/// An item and where it was written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub item: Item,
+    pub path: Option<Rc<Path>>,
+    pub line: usize,
+}
+
+impl Line {
+    /// An error at this line.
+    pub fn error(&self, message: String) -> ScriptError {
+        ScriptError {
+            path: self.path.as_deref().map(Path::to_owned),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script {
+    pub lines: Vec<Line>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptError {
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}:{}: {}", path.display(), self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// A script given as text, which includes files relative to the working directory.
+impl std::str::FromStr for Script {
+    type Err = ScriptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut script = Script::default();
+        parse(s, None, &mut Vec::new(), &mut script)?;
+        Ok(script)
+    }
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self, ScriptError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ScriptError {
+            path: Some(path.to_owned()),
+            line: 0,
+            message: e.to_string(),
+        })?;
+        let mut script = Script::default();
+        let mut including = vec![std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())];
+        parse(&text, Some(path), &mut including, &mut script)?;
+        Ok(script)
+    }
+}
+
+/// Appends the lines of `text`, read from `path`, to `script`. `including` holds the canonical
+/// paths of the files being included, to catch cycles.
+fn parse(
+    text: &str,
+    path: Option<&Path>,
+    including: &mut Vec<PathBuf>,
+    script: &mut Script,
+) -> Result<(), ScriptError> {
+    let source: Option<Rc<Path>> = path.map(Rc::from);
+    for (i, text) in text.lines().enumerate() {
+        let line = i + 1;
+        let err = |message: String| ScriptError {
+            path: path.map(Path::to_owned),
+            line,
+            message,
+        };
+        if text.starts_with("//") {
+            continue;
+        }
+        let Some(directive) = text.strip_prefix('@') else {
+            script.lines.push(Line {
+                item: Item::Input(text.to_owned()),
+                path: source.clone(),
+                line,
+            });
+            continue;
+        };
+        let (name, args) = directive
+            .trim()
+            .split_once(' ')
+            .unwrap_or((directive.trim(), ""));
+        let args = args.trim();
+        let words: Vec<&str> = args.split_whitespace().collect();
+        let item = match (name, &words[..]) {
+            ("expect", [_, ..]) => Item::Expect(
+                args.strip_prefix('"')
+                    .and_then(|a| a.strip_suffix('"'))
+                    .filter(|a| !a.is_empty())
+                    .ok_or_else(|| err(format!("expected a quoted string, found {args:?}")))?
+                    .to_owned(),
+            ),
+            ("setreg", [reg, val]) => {
+                let reg = reg
+                    .parse()
+                    .map_err(|_| err(format!("invalid register {reg:?}")))?;
+                Item::SetReg(reg, Some(parse_number(val).map_err(err)?))
+            }
+            ("setreg", ["r7"]) => Item::SetReg(Reg::REG7, None),
+            ("patch", [addr, vals @ ..]) if !vals.is_empty() => {
+                let addr = u16::from_str_radix(addr.trim_start_matches("0x"), 16)
+                    .map_err(|e| err(format!("invalid address {addr:?}: {e}")))?;
+                let vals = vals
+                    .iter()
+                    .map(|v| parse_number(v))
+                    .collect::<Result<_, _>>()
+                    .map_err(err)?;
+                Item::Patch(addr, vals)
+            }
+            ("snapshot", [name]) => Item::Snapshot((*name).to_owned()),
+            ("include", [_, ..]) => {
+                let file = match path.and_then(Path::parent) {
+                    Some(dir) => dir.join(args),
+                    None => PathBuf::from(args),
+                };
+                let canonical = std::fs::canonicalize(&file)
+                    .map_err(|e| err(format!("{}: {e}", file.display())))?;
+                if including.contains(&canonical) {
+                    return Err(err(format!("{} includes itself", file.display())));
+                }
+                let text = std::fs::read_to_string(&file)
+                    .map_err(|e| err(format!("{}: {e}", file.display())))?;
+                including.push(canonical);
+                parse(&text, Some(&file), including, script)?;
+                including.pop();
+                continue;
+            }
+            ("pause", []) => Item::Pause,
+            ("quiet", []) => Item::Quiet,
+            ("echo", []) => Item::Echo,
+            (
+                "expect" | "setreg" | "patch" | "snapshot" | "include" | "pause" | "quiet" | "echo",
+                _,
+            ) => return Err(err(format!("wrong arguments to @{name}"))),
+            _ => return Err(err(format!("unknown directive @{name}"))),
+        };
+        script.lines.push(Line {
+            item,
+            path: source.clone(),
+            line,
+        });
+    }
+    Ok(())
+}
+
+/// A 15-bit number, decimal unless prefixed with `0x`.
+fn parse_number(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .ok()
+    .filter(|n| *n < crate::machine::MOD)
+    .ok_or_else(|| format!("invalid number {s:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_directives() {
+        let script: Script = "\
+// comment
+take tablet
+@expect \"Taken.\"
+@setreg r7 25734
+@patch 0x1571 21 0x15
+
+@snapshot start
+@pause
+@quiet
+@echo
+"
+        .parse()
+        .unwrap();
+        let items: Vec<_> = script.lines.iter().map(|l| l.item.clone()).collect();
+        assert_eq!(
+            items,
+            [
+                Item::Input("take tablet".to_owned()),
+                Item::Expect("Taken.".to_owned()),
+                Item::SetReg(Reg::REG7, Some(25734)),
+                Item::Patch(0x1571, vec![21, 21]),
+                Item::Input(String::new()),
+                Item::Snapshot("start".to_owned()),
+                Item::Pause,
+                Item::Quiet,
+                Item::Echo,
+            ]
+        );
+        assert_eq!(script.lines[1].line, 3);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let cases = [
+            ("look\n@jump", 2, "unknown directive @jump"),
+            ("@expect Taken.", 1, "expected a quoted string"),
+            ("\n\n@setreg r8 1", 3, "invalid register \"r8\""),
+            ("@setreg r7 32768", 1, "invalid number \"32768\""),
+            ("@patch 0x10", 1, "wrong arguments to @patch"),
+            ("@pause now", 1, "wrong arguments to @pause"),
+            ("@include no-such-file.txt", 1, "no-such-file.txt"),
+        ];
+        for (input, line, message) in cases {
+            let err = input.parse::<Script>().unwrap_err();
+            assert_eq!(err.line, line, "{input:?}: {err}");
+            assert!(err.message.contains(message), "{input:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn includes_relative_to_the_script() {
+        let dir = std::env::temp_dir().join(format!("vmc-{}-script", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.txt"), "look\n@include part.txt\ninv\n").unwrap();
+        std::fs::write(dir.join("part.txt"), "// part\n@bad\n").unwrap();
+        let err = Script::load(&dir.join("main.txt")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}:2: unknown directive @bad",
+                dir.join("part.txt").display()
+            )
+        );
+
+        std::fs::write(dir.join("part.txt"), "take tablet\n@include main.txt\n").unwrap();
+        let err = Script::load(&dir.join("main.txt")).unwrap_err();
+        assert!(err.message.ends_with("main.txt includes itself"), "{err}");
+
+        // The same file by another path.
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("part.txt"), "@include sub/a.txt\n").unwrap();
+        std::fs::write(dir.join("sub/a.txt"), "@include ../main.txt\n").unwrap();
+        let err = Script::load(&dir.join("main.txt")).unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(
+            err.message.ends_with("../main.txt includes itself"),
+            "{err}"
+        );
+
+        std::fs::write(dir.join("part.txt"), "// part\ntake tablet\n").unwrap();
+        let script = Script::load(&dir.join("main.txt")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let lines: Vec<_> = script
+            .lines
+            .iter()
+            .map(|l| {
+                (
+                    l.path.as_deref().unwrap().file_name().unwrap().to_owned(),
+                    l.line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                ("main.txt".into(), 1),
+                ("part.txt".into(), 2),
+                ("main.txt".into(), 3)
+            ]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! Finding routines by the shape of their code rather than their address, so the tools work on
+//! any challenge binary.
+//!
+//! A pattern is a `;`-separated list of items. An item is either an op, written like the
+//! disassembly without commas (`add r0 r0 0x7fff`), or raw words (`9 32768 ?`). `?` matches any
+//! word and captures it. The match is reported at the first item, or at the item marked with
+//! `>`.
+
+use crate::op::Reg;
+
+/// Mnemonics by opcode, with their operand counts.
+const OPS: [(&str, usize); 22] = [
+    ("halt", 0),
+    ("set", 2),
+    ("push", 1),
+    ("pop", 1),
+    ("eq", 3),
+    ("gt", 3),
+    ("jmp", 1),
+    ("jt", 2),
+    ("jf", 2),
+    ("add", 3),
+    ("mult", 3),
+    ("mod", 3),
+    ("and", 3),
+    ("or", 3),
+    ("not", 2),
+    ("rmem", 2),
+    ("wmem", 2),
+    ("call", 1),
+    ("ret", 0),
+    ("out", 1),
+    ("in", 1),
+    ("noop", 0),
+];
+
+/// Routines the tools know how to find, as `(name, pattern)`.
+pub const KNOWN: &[(&str, &str)] = &[
+    (
+        "recursive_func",
+        "jt r0 ?; add r0 r1 1; ret; jt r1 ?; add r0 r0 0x7fff; set r1 r7; call ?; ret",
+    ),
+    // Captures the address of `recursive_func`.
+    (
+        "teleporter_check",
+        "set r0 4; set r1 1; >call ?; eq r1 r0 6",
+    ),
+    // Calls r1 with every character of the length-prefixed string at r0.
+    (
+        "string_for_each",
+        "push r0; push r3; push r4; push r5; push r6; set r6 r0; set r5 r1; rmem r4 r0; \
+         set r1 0; add r3 1 r1; gt r0 r3 r4; jt r0 ?; add r3 r3 r6; rmem r0 r3; call r5",
+    ),
+    // `string_for_each` with a callback that outputs each character.
+    (
+        "print_string",
+        "push r1; set r1 ?; call ?; pop r1; ret; out r0; ret",
+    ),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    /// `None` for a wildcard.
+    words: Vec<Option<u16>>,
+    /// Word offset of the reported address from the start of the match.
+    anchor: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub addr: u16,
+    /// The words matched by each `?`, in order.
+    pub captures: Vec<u16>,
+}
+
+#[derive(Debug)]
+pub struct ParseSignatureError {
+    /// 1-based index of the offending item.
+    pub item: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseSignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pattern item {}: {}", self.item, self.message)
+    }
+}
+
+impl std::error::Error for ParseSignatureError {}
+
+impl std::str::FromStr for Signature {
+    type Err = ParseSignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = Vec::new();
+        let mut anchor = None;
+        for (i, item) in s.split(';').enumerate() {
+            let err = |message: String| ParseSignatureError {
+                item: i + 1,
+                message,
+            };
+            let mut item = item.trim();
+            if let Some(rest) = item.strip_prefix('>') {
+                if anchor.is_some() {
+                    return Err(err("more than one item is marked with '>'".to_owned()));
+                }
+                anchor = Some(words.len());
+                item = rest.trim_start();
+            }
+            let mut tokens = item.split_whitespace().peekable();
+            let Some(&first) = tokens.peek() else {
+                return Err(err("empty item".to_owned()));
+            };
+            if let Some(opcode) = OPS.iter().position(|(name, _)| *name == first) {
+                tokens.next();
+                let args: Vec<_> = tokens.collect();
+                let want = OPS[opcode].1;
+                if args.len() != want {
+                    return Err(err(format!(
+                        "{first} takes {want} operands, got {}",
+                        args.len()
+                    )));
+                }
+                words.push(Some(opcode as u16));
+                for arg in args {
+                    words.push(operand(arg).map_err(err)?);
+                }
+            } else {
+                for token in tokens {
+                    words.push(operand(token).map_err(err)?);
+                }
+            }
+        }
+        Ok(Signature {
+            words,
+            anchor: anchor.unwrap_or(0),
+        })
+    }
+}
+
+/// `?`, a register `r0`..`r7`, or a decimal or `0x` hex word.
+fn operand(s: &str) -> Result<Option<u16>, String> {
+    if s == "?" {
+        return Ok(None);
+    }
+    if let Some(n) = s.strip_prefix('r') {
+        let reg = n
+            .parse::<u8>()
+            .ok()
+            .and_then(|n| Reg::try_from(n).ok())
+            .ok_or_else(|| format!("invalid register {s:?}"))?;
+        return Ok(Some(reg.into()));
+    }
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map(Some)
+    .map_err(|e| format!("invalid operand {s:?}: {e}"))
+}
+
+impl Signature {
+    /// Every place in `mem` the pattern matches, in address order. Matches may overlap.
+    pub fn scan(&self, mem: &[u16]) -> Vec<Match> {
+        if self.words.is_empty() {
+            return Vec::new();
+        }
+        mem.windows(self.words.len())
+            .enumerate()
+            .filter(|(_, window)| {
+                self.words
+                    .iter()
+                    .zip(*window)
+                    .all(|(want, got)| want.is_none_or(|w| w == *got))
+            })
+            .map(|(start, window)| Match {
+                addr: (start + self.anchor) as u16,
+                captures: self
+                    .words
+                    .iter()
+                    .zip(window)
+                    .filter(|(want, _)| want.is_none())
+                    .map(|(_, got)| *got)
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// The first match of the [`KNOWN`] signature called `name`.
+pub fn find(mem: &[u16], name: &str) -> Option<Match> {
+    let (_, pattern) = KNOWN.iter().find(|(n, _)| *n == name)?;
+    let sig: Signature = pattern.parse().expect("built-in signatures parse");
+    sig.scan(mem).into_iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::machine::load_rom;
+
+    #[test]
+    fn parses_ops_and_raw_words() {
+        let sig: Signature = "add r0 r0 0x7fff; >? 32769".parse().unwrap();
+        assert_eq!(
+            sig.words,
+            [
+                Some(9),
+                Some(32768),
+                Some(32768),
+                Some(0x7fff),
+                None,
+                Some(32769)
+            ]
+        );
+        assert_eq!(sig.anchor, 4);
+    }
+
+    #[test]
+    fn parse_errors_name_the_item() {
+        for (pattern, item, message) in [
+            ("ret; add r0 r1", 2, "add takes 3 operands, got 2"),
+            ("jt r8 ?", 1, "invalid register \"r8\""),
+            ("ret;", 2, "empty item"),
+            (">ret; >ret", 2, "more than one item is marked with '>'"),
+        ] {
+            let err = pattern.parse::<Signature>().unwrap_err();
+            assert_eq!(
+                (err.item, err.message.as_str()),
+                (item, message),
+                "{pattern}"
+            );
+        }
+    }
+
+    #[test]
+    fn scan_captures_wildcards() {
+        let mem = [21, 17, 0x10, 21, 17, 0x20];
+        let sig: Signature = "noop; >call ?".parse().unwrap();
+        let found = sig.scan(&mem);
+        assert_eq!(
+            found,
+            [
+                Match {
+                    addr: 1,
+                    captures: vec![0x10]
+                },
+                Match {
+                    addr: 4,
+                    captures: vec![0x20]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_known_routines() {
+        let mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let addr = |name| find(&mem, name).map(|m| m.addr);
+        assert_eq!(addr("recursive_func"), Some(0x17a1));
+        assert_eq!(addr("teleporter_check"), Some(0x1587));
+        assert_eq!(addr("string_for_each"), Some(0x05c8));
+        assert_eq!(addr("print_string"), Some(0x0604));
+        assert_eq!(find(&mem, "teleporter_check").unwrap().captures, [0x17a1]);
+    }
+}
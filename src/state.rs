@@ -0,0 +1,382 @@
+//! The game's state read straight out of memory: the room the player is in, what they carry
+//! and where every item is, as laid out by the annotations.
+//!
+//! The layout takes the `current_room` variable, the `items` data region, `[struct item]`
+//! with `name`, `description` and `location` fields, `[struct room]` with `name`,
+//! `description`, `exits` and `exit_rooms` fields, and `[enum location]` with the
+//! `inventory` and `nowhere` locations. Any other location is the address of a room.
+//!
+//! Strings are only readable once the ROM has decrypted them during its self-test.
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    annotations::{Annotations, Struct},
+    machine::MOD,
+};
+
+/// Where the game keeps its state, taken from the annotations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub current_room: u16,
+    /// Address of the first item and the number of items.
+    pub items: (u16, u16),
+    pub item: ItemLayout,
+    pub room: RoomLayout,
+    /// The locations of carried and of used up items.
+    pub inventory: u16,
+    pub nowhere: u16,
+}
+
+/// Field offsets of `[struct item]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ItemLayout {
+    pub size: u16,
+    pub name: u16,
+    pub description: u16,
+    pub location: u16,
+}
+
+/// Field offsets of `[struct room]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoomLayout {
+    pub name: u16,
+    pub description: u16,
+    pub exits: u16,
+    pub exit_rooms: u16,
+}
+
+impl Layout {
+    pub fn from_annotations(annotations: &Annotations) -> Result<Self, String> {
+        let current_room = annotations
+            .variables
+            .iter()
+            .find(|(_, v)| v.name == "current_room")
+            .map(|(a, _)| *a)
+            .ok_or("no current_room variable")?;
+        let items = annotations
+            .data
+            .values()
+            .find(|d| d.name == "items")
+            .ok_or("no items data region")?;
+        let structure = |name: &str| {
+            annotations
+                .structs
+                .get(name)
+                .ok_or_else(|| format!("no [struct {name}]"))
+        };
+        let field = |s: &Struct, name: &str, field: &str| {
+            s.field(field)
+                .ok_or_else(|| format!("struct {name} has no {field} field"))
+        };
+        let item = structure("item")?;
+        let room = structure("room")?;
+        let location = annotations
+            .enums
+            .get("location")
+            .ok_or("no [enum location]")?;
+        let location = |name: &str| {
+            location
+                .values
+                .iter()
+                .find(|(_, n)| *n == name)
+                .map(|(v, _)| *v)
+                .ok_or_else(|| format!("enum location has no {name}"))
+        };
+        if item.size == 0 {
+            return Err("struct item has no size".to_owned());
+        }
+        if items
+            .start
+            .checked_add(items.len)
+            .is_none_or(|end| end > MOD)
+        {
+            return Err("the items data region runs past the end of memory".to_owned());
+        }
+        Ok(Layout {
+            current_room,
+            items: (items.start, items.len / item.size),
+            item: ItemLayout {
+                size: item.size,
+                name: field(item, "item", "name")?,
+                description: field(item, "item", "description")?,
+                location: field(item, "item", "location")?,
+            },
+            room: RoomLayout {
+                name: field(room, "room", "name")?,
+                description: field(room, "room", "description")?,
+                exits: field(room, "room", "exits")?,
+                exit_rooms: field(room, "room", "exit_rooms")?,
+            },
+            inventory: location("inventory")?,
+            nowhere: location("nowhere")?,
+        })
+    }
+
+    /// Addresses of the item records.
+    pub fn item_addrs(&self) -> impl Iterator<Item = u16> + use<> {
+        let ((start, count), size) = (self.items, self.item.size);
+        (0..count).map_while(move |i| i.checked_mul(size)?.checked_add(start))
+    }
+
+    pub fn room(&self, mem: &[u16], addr: u16) -> Result<Room, String> {
+        let exits = list(mem, field(mem, addr, self.room.exits)?)?;
+        let exit_rooms = list(mem, field(mem, addr, self.room.exit_rooms)?)?;
+        let exits = exits
+            .into_iter()
+            .zip(exit_rooms)
+            .map(|(name, to)| Ok((string(mem, name)?, to)))
+            .collect::<Result<_, String>>()?;
+        Ok(Room {
+            addr,
+            name: string(mem, field(mem, addr, self.room.name)?)?,
+            description: string(mem, field(mem, addr, self.room.description)?).ok(),
+            exits,
+        })
+    }
+
+    pub fn item(&self, mem: &[u16], addr: u16) -> Result<Item, String> {
+        let location = match field(mem, addr, self.item.location)? {
+            l if l == self.inventory => Location::Inventory,
+            l if l == self.nowhere => Location::Nowhere,
+            l => Location::Room(l),
+        };
+        Ok(Item {
+            addr,
+            name: string(mem, field(mem, addr, self.item.name)?)?,
+            description: string(mem, field(mem, addr, self.item.description)?)?,
+            location,
+        })
+    }
+
+    /// The item called `name`.
+    pub fn find_item(&self, mem: &[u16], name: &str) -> Result<Item, String> {
+        for addr in self.item_addrs() {
+            let item = self.item(mem, addr)?;
+            if item.name == name {
+                return Ok(item);
+            }
+        }
+        Err(format!("no item {name:?}"))
+    }
+
+    /// Every room that can be found from the current room and the items' locations by
+    /// following exits, by address.
+    pub fn rooms(&self, mem: &[u16]) -> Result<Vec<Room>, String> {
+        let mut queue = VecDeque::from([word(mem, self.current_room)?]);
+        for addr in self.item_addrs() {
+            if let Location::Room(at) = self.item(mem, addr)?.location {
+                queue.push_back(at);
+            }
+        }
+        let mut seen = HashSet::new();
+        let mut rooms = Vec::new();
+        while let Some(addr) = queue.pop_front() {
+            if !seen.insert(addr) {
+                continue;
+            }
+            let room = self.room(mem, addr)?;
+            queue.extend(room.exits.iter().map(|(_, to)| *to));
+            rooms.push(room);
+        }
+        rooms.sort_by_key(|r| r.addr);
+        Ok(rooms)
+    }
+
+    pub fn decode(&self, mem: &[u16]) -> Result<State, String> {
+        let room = self.room(mem, word(mem, self.current_room)?)?;
+        let mut items = Vec::new();
+        for addr in self.item_addrs() {
+            let item = self.item(mem, addr)?;
+            let room = match item.location {
+                Location::Room(at) => Some(self.room(mem, at)?.name),
+                _ => None,
+            };
+            items.push((item, room));
+        }
+        Ok(State { room, items })
+    }
+}
+
+fn word(mem: &[u16], addr: u16) -> Result<u16, String> {
+    mem.get(addr as usize)
+        .copied()
+        .ok_or_else(|| format!("0x{addr:04x} is out of memory"))
+}
+
+/// The field at `offset` in the record at `addr`, which can be any word read from the game.
+fn field(mem: &[u16], addr: u16, offset: u16) -> Result<u16, String> {
+    let at = addr
+        .checked_add(offset)
+        .ok_or_else(|| format!("no record at 0x{addr:04x}"))?;
+    word(mem, at)
+}
+
+/// The length-prefixed list of words at `addr`.
+fn list(mem: &[u16], addr: u16) -> Result<Vec<u16>, String> {
+    let len = word(mem, addr)?;
+    let start = addr as usize + 1;
+    mem.get(start..start + len as usize)
+        .map(<[u16]>::to_vec)
+        .ok_or_else(|| format!("list at 0x{addr:04x} runs out of memory"))
+}
+
+/// The length-prefixed string at `addr`.
+fn string(mem: &[u16], addr: u16) -> Result<String, String> {
+    let chars = list(mem, addr)?;
+    if !chars.iter().all(|&c| c < 0x80) {
+        return Err(format!("no string at 0x{addr:04x}; has the ROM booted?"));
+    }
+    Ok(chars.into_iter().map(|c| c as u8 as char).collect())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Room {
+    pub addr: u16,
+    pub name: String,
+    /// `None` if it isn't a string, as for one of the passages.
+    pub description: Option<String>,
+    /// Exit names and the addresses of the rooms they lead to.
+    pub exits: Vec<(String, u16)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Location {
+    Inventory,
+    Nowhere,
+    /// Address of a room.
+    Room(u16),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Item {
+    pub addr: u16,
+    pub name: String,
+    pub description: String,
+    pub location: Location,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct State {
+    pub room: Room,
+    /// Every item, with the name of the room it's in if it's in one.
+    pub items: Vec<(Item, Option<String>)>,
+}
+
+impl State {
+    pub fn inventory(&self) -> impl Iterator<Item = &Item> {
+        self.items
+            .iter()
+            .map(|(item, _)| item)
+            .filter(|item| item.location == Location::Inventory)
+    }
+}
+
+/// ```text
+/// room: Foothills (0x0923)
+/// exits: doorway (0x092d), south (0x0928)
+/// inventory: tablet
+/// items:
+///   0x0a82 tablet: inventory
+///   0x0a86 empty lantern: nowhere
+///   0x0aae business card: Synacor Headquarters (0x09ce)
+/// ```
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "room: {} (0x{:04x})", self.room.name, self.room.addr)?;
+        let exits: Vec<_> = self
+            .room
+            .exits
+            .iter()
+            .map(|(name, to)| format!("{name} (0x{to:04x})"))
+            .collect();
+        writeln!(f, "exits: {}", exits.join(", "))?;
+        let inventory: Vec<_> = self.inventory().map(|i| i.name.as_str()).collect();
+        writeln!(f, "inventory: {}", inventory.join(", "))?;
+        writeln!(f, "items:")?;
+        for (item, room) in &self.items {
+            write!(f, "  0x{:04x} {}: ", item.addr, item.name)?;
+            match (item.location, room) {
+                (Location::Inventory, _) => writeln!(f, "inventory")?,
+                (Location::Room(at), Some(room)) => writeln!(f, "{room} (0x{at:04x})")?,
+                (Location::Room(at), None) => writeln!(f, "0x{at:04x}")?,
+                (Location::Nowhere, _) => writeln!(f, "nowhere")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{
+        annotations::load_annotations,
+        error::Error,
+        machine::{Buffer, Machine, load_rom},
+    };
+
+    fn layout() -> Layout {
+        let annotations = load_annotations(Path::new("annotations.ini"))
+            .unwrap()
+            .unwrap();
+        Layout::from_annotations(&annotations).unwrap()
+    }
+
+    #[test]
+    fn decodes_the_game() {
+        let layout = layout();
+        let mem = load_rom(Path::new("challenge.bin")).unwrap();
+        let mut machine = Machine::with_console(mem, Buffer::default());
+        assert!(layout.decode(machine.mem()).is_err());
+        assert!(matches!(machine.run(), Err(Error::InputExhausted(_))));
+
+        let state = layout.decode(machine.mem()).unwrap();
+        assert_eq!(state.room.name, "Foothills");
+        let exits: Vec<_> = state.room.exits.iter().map(|(e, _)| e.as_str()).collect();
+        assert_eq!(exits, ["doorway", "south"]);
+        assert_eq!(state.inventory().count(), 0);
+        let tablet = layout.find_item(machine.mem(), "tablet").unwrap();
+        assert_eq!(tablet.location, Location::Room(state.room.addr));
+        assert_eq!(
+            layout.find_item(machine.mem(), "lantern").unwrap().location,
+            Location::Nowhere
+        );
+
+        machine.command("take tablet").unwrap();
+        machine.command("go doorway").unwrap();
+        let state = layout.decode(machine.mem()).unwrap();
+        assert_eq!(state.room.name, "Dark cave");
+        let inventory: Vec<_> = state.inventory().map(|i| i.name.as_str()).collect();
+        assert_eq!(inventory, ["tablet"]);
+        assert!(state.to_string().contains("\n  0x0a82 tablet: inventory\n"));
+    }
+
+    #[test]
+    fn needs_a_complete_layout() {
+        let annotations = crate::annotations::parse_annotations(
+            "[variables]\n0x0ac2 = \"current_room\"".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            Layout::from_annotations(&annotations),
+            Err("no items data region".to_owned())
+        );
+    }
+
+    #[test]
+    fn records_past_the_end_of_memory_are_errors() {
+        let layout = layout();
+        let mem = vec![0; MOD as usize];
+        assert_eq!(
+            layout.room(&mem, 0xffff),
+            Err("no record at 0xffff".to_owned())
+        );
+        assert_eq!(
+            layout.item(&mem, 0x7fff),
+            Err("0x8001 is out of memory".to_owned())
+        );
+    }
+}
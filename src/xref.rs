@@ -0,0 +1,195 @@
+//! A cross-reference database: who calls, jumps to, reads, writes or takes the address of
+//! each address, and which strings are referred to.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    annotations::Annotations,
+    dataflow::Dataflow,
+    functions::Functions,
+    op::{Op, Val},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Call,
+    Jump,
+    /// `rmem` from a constant address.
+    Read,
+    /// `wmem` to a constant address.
+    Write,
+    /// A constant that names code or data, e.g. a callback passed in a register.
+    Address,
+    /// A constant that is the address of a string.
+    String,
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Kind::Call => "call",
+            Kind::Jump => "jump",
+            Kind::Read => "read",
+            Kind::Write => "write",
+            Kind::Address => "address",
+            Kind::String => "string",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ref {
+    /// The op doing the referring.
+    pub from: u16,
+    pub to: u16,
+    pub kind: Kind,
+    /// Entry of a function the referring op is part of.
+    pub function: Option<u16>,
+}
+
+pub struct Xrefs {
+    to: BTreeMap<u16, Vec<Ref>>,
+    from: BTreeMap<u16, Vec<Ref>>,
+    /// Length-prefixed printable strings, by address.
+    pub strings: BTreeMap<u16, String>,
+    /// Names of the functions refs are made from.
+    pub function_names: BTreeMap<u16, String>,
+}
+
+impl Xrefs {
+    pub fn build(mem: &[u16], annotations: &Annotations) -> Xrefs {
+        let funcs = Functions::analyze(mem, annotations);
+        let dataflow = Dataflow::analyze(&funcs);
+        let mut owner = HashMap::new();
+        for f in funcs.functions.values().rev() {
+            for addr in &f.body {
+                owner.insert(*addr, f.entry);
+            }
+        }
+        let mut xrefs = Xrefs {
+            to: BTreeMap::new(),
+            from: BTreeMap::new(),
+            strings: strings(mem),
+            function_names: funcs
+                .functions
+                .values()
+                .map(|f| (f.entry, f.name.clone()))
+                .collect(),
+        };
+        let is_address = |v: u16| {
+            funcs.functions.contains_key(&v)
+                || annotations.labels.contains_key(&v)
+                || annotations.data_containing(v).is_some()
+                || annotations.variables.contains_key(&v)
+        };
+        for (&from, op) in &funcs.ops {
+            let mut add = |to: u16, kind: Kind| {
+                let r = Ref {
+                    from,
+                    to,
+                    kind,
+                    function: owner.get(&from).copied(),
+                };
+                xrefs.to.entry(to).or_default().push(r);
+                xrefs.from.entry(from).or_default().push(r);
+            };
+            let resolved = dataflow.resolve(from, op).unwrap_or_default();
+            match *op {
+                Op::Call(Val::Literal(to)) => add(to, Kind::Call),
+                Op::Call(Val::Reg(_)) => resolved.iter().for_each(|&to| add(to, Kind::Call)),
+                Op::Jmp(Val::Literal(to))
+                | Op::Jt(_, Val::Literal(to))
+                | Op::Jf(_, Val::Literal(to)) => add(to, Kind::Jump),
+                Op::Jmp(_) | Op::Jt(..) | Op::Jf(..) => {
+                    resolved.iter().for_each(|&to| add(to, Kind::Jump))
+                }
+                Op::Rmem(_, Val::Literal(at)) => add(at, Kind::Read),
+                _ => {}
+            }
+            if let Op::Wmem(Val::Literal(at), _) = *op {
+                add(at, Kind::Write);
+            }
+            if let Op::Set(_, Val::Literal(v)) | Op::Wmem(_, Val::Literal(v)) = *op {
+                if xrefs.strings.contains_key(&v) {
+                    add(v, Kind::String);
+                } else if is_address(v) {
+                    add(v, Kind::Address);
+                }
+            }
+        }
+        xrefs
+    }
+
+    /// References to `addr`, in address order of the referring ops.
+    pub fn to(&self, addr: u16) -> &[Ref] {
+        self.to.get(&addr).map_or(&[], Vec::as_slice)
+    }
+
+    /// References made by the op at `addr`.
+    pub fn from(&self, addr: u16) -> &[Ref] {
+        self.from.get(&addr).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Runs of a length word followed by that many printable characters. At least two, since
+/// almost any pair of words looks like a one-character string.
+fn strings(mem: &[u16]) -> BTreeMap<u16, String> {
+    let printable = |w: u16| (0x20..0x7f).contains(&w) || w == b'\n' as u16;
+    let mut strings = BTreeMap::new();
+    let mut addr = 0;
+    while addr < mem.len() {
+        let len = mem[addr] as usize;
+        let text = mem.get(addr + 1..addr + 1 + len);
+        match text {
+            Some(text) if len >= 2 && text.iter().all(|&w| printable(w)) => {
+                let s = text.iter().map(|&w| w as u8 as char).collect();
+                strings.insert(addr as u16, s);
+                addr += 1 + len;
+            }
+            _ => addr += 1,
+        }
+    }
+    strings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{annotations::Variable, op::Reg};
+
+    #[test]
+    fn finds_code_data_and_string_refs() {
+        let mut mem = Vec::new();
+        for op in [
+            /* 0 */ Op::Set(Reg::REG0, Val::Literal(20)),
+            /* 3 */ Op::Call(Val::Literal(14)),
+            /* 5 */ Op::Rmem(Reg::REG1, Val::Literal(24)),
+            /* 8 */ Op::Wmem(Val::Literal(24), Val::Literal(14)),
+            /* 11 */ Op::Jmp(Val::Literal(0)),
+            /* 13 */ Op::Halt,
+            /* 14 */ Op::Out(Val::Reg(Reg::REG0)),
+            /* 16 */ Op::Ret,
+        ] {
+            op.encode(&mut mem);
+        }
+        mem.resize(20, 0);
+        mem.extend([3, 'h' as u16, 'i' as u16, '!' as u16, 0]);
+        let mut annotations = Annotations::default();
+        annotations.variables.insert(
+            24,
+            Variable {
+                name: "v".to_owned(),
+                ty: None,
+            },
+        );
+        let xrefs = Xrefs::build(&mem, &annotations);
+        let refs = |to| -> Vec<_> { xrefs.to(to).iter().map(|r| (r.from, r.kind)).collect() };
+        assert_eq!(refs(14), [(3, Kind::Call), (8, Kind::Address)]);
+        assert_eq!(refs(0), [(11, Kind::Jump)]);
+        assert_eq!(refs(24), [(5, Kind::Read), (8, Kind::Write)]);
+        assert_eq!(refs(20), [(0, Kind::String)]);
+        assert_eq!(xrefs.strings[&20], "hi!");
+        assert_eq!(xrefs.from(3)[0].function, Some(0));
+    }
+}
@@ -0,0 +1,361 @@
+//! Property tests: random valid programs and initial states must behave identically on
+//! `Machine` and on a reference interpreter written from the arch-spec, and every op must
+//! survive a decode → encode → decode round trip.
+
+use std::collections::VecDeque;
+
+use vmc::{
+    error::Error,
+    machine::{Buffer, Machine, Mode},
+    op::{Op, Reg, Val},
+};
+
+const CASES: u64 = 500;
+const MAX_STEPS: usize = 200;
+
+/// xorshift64*, enough randomness for generating programs without pulling in a crate.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn reg(&mut self) -> Reg {
+        Reg::try_from(self.below(8) as u8).unwrap()
+    }
+
+    /// A literal biased towards the edges of 15-bit arithmetic.
+    fn literal(&mut self) -> u16 {
+        match self.below(4) {
+            0 => [0, 1, 2, 0x7ffe, 0x7fff][self.below(5) as usize],
+            1 => self.below(128) as u16,
+            _ => self.below(1 << 15) as u16,
+        }
+    }
+
+    fn val(&mut self) -> Val {
+        if self.below(2) == 0 {
+            Val::Reg(self.reg())
+        } else {
+            Val::Literal(self.literal())
+        }
+    }
+}
+
+/// Words after the program that `rmem` and `wmem` use as scratch memory.
+const DATA_LEN: u16 = 16;
+
+/// Generates a program of `count` ops. Literal jump targets are addresses of ops in the program
+/// and literal `rmem`/`wmem` addresses point into the program or the data after it. Register
+/// operands can hold anything, including encoded operands (32768..) read back by `rmem`.
+fn program(rng: &mut Rng, count: usize) -> Vec<u16> {
+    // Addresses are only known once every op is sized, so targets are picked in a second pass.
+    let ops: Vec<Op> = (0..count).map(|_| random_op(rng)).collect();
+    let mut starts = Vec::with_capacity(count);
+    let mut len = 0u16;
+    for op in &ops {
+        starts.push(len);
+        len += 1 + op.arg_count() as u16;
+    }
+    let mut words = Vec::new();
+    for op in ops {
+        let op = retarget(rng, op, &starts, len);
+        op.encode(&mut words);
+    }
+    Op::Halt.encode(&mut words);
+    words
+}
+
+fn random_op(rng: &mut Rng) -> Op {
+    match rng.below(22) {
+        0 => Op::Halt,
+        1 => Op::Set(rng.reg(), rng.val()),
+        2 => Op::Push(rng.val()),
+        3 => Op::Pop(rng.reg()),
+        4 => Op::Eq(rng.reg(), rng.val(), rng.val()),
+        5 => Op::Gt(rng.reg(), rng.val(), rng.val()),
+        6 => Op::Jmp(rng.val()),
+        7 => Op::Jt(rng.val(), rng.val()),
+        8 => Op::Jf(rng.val(), rng.val()),
+        9 => Op::Add(rng.reg(), rng.val(), rng.val()),
+        10 => Op::Mult(rng.reg(), rng.val(), rng.val()),
+        11 => Op::Mod(rng.reg(), rng.val(), rng.val()),
+        12 => Op::And(rng.reg(), rng.val(), rng.val()),
+        13 => Op::Or(rng.reg(), rng.val(), rng.val()),
+        14 => Op::Not(rng.reg(), rng.val()),
+        15 => Op::Rmem(rng.reg(), rng.val()),
+        16 => Op::Wmem(rng.val(), rng.val()),
+        17 => Op::Call(rng.val()),
+        18 => Op::Ret,
+        19 => Op::Out(rng.val()),
+        20 => Op::In(rng.reg()),
+        _ => Op::Noop,
+    }
+}
+
+fn retarget(rng: &mut Rng, op: Op, starts: &[u16], len: u16) -> Op {
+    let mut target = |v: Val| match v {
+        Val::Literal(_) => Val::Literal(starts[rng.below(starts.len() as u64) as usize]),
+        reg => reg,
+    };
+    let op = match op {
+        Op::Jmp(a) => Op::Jmp(target(a)),
+        Op::Jt(a, b) => Op::Jt(a, target(b)),
+        Op::Jf(a, b) => Op::Jf(a, target(b)),
+        Op::Call(a) => Op::Call(target(a)),
+        op => op,
+    };
+    let mut data = |v: Val| match v {
+        Val::Literal(_) => Val::Literal(rng.below((len + DATA_LEN) as u64) as u16),
+        reg => reg,
+    };
+    match op {
+        Op::Rmem(a, b) => Op::Rmem(a, data(b)),
+        Op::Wmem(a, b) => Op::Wmem(data(a), b),
+        op => op,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct State {
+    pc: usize,
+    registers: [u16; 8],
+    stack: Vec<u16>,
+    output: Vec<u8>,
+}
+
+/// How a step ended, for comparing the engines' errors.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Ran,
+    Halted,
+    InputExhausted,
+    /// Anything the arch-spec calls an error, such as an invalid op.
+    Fault,
+}
+
+/// An interpreter written straight from the arch-spec, with the fallbacks of
+/// [`Mode::Lenient`], sharing no code with `Machine`: it decodes the raw words itself.
+struct Reference {
+    mem: Vec<u16>,
+    pc: usize,
+    registers: [u16; 8],
+    stack: Vec<u16>,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Reference {
+    fn new(mut mem: Vec<u16>, registers: &[u16; 8], input: &[u8]) -> Self {
+        mem.resize(1 << 15, 0);
+        Reference {
+            mem,
+            pc: 0,
+            registers: *registers,
+            stack: Vec::new(),
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+
+    /// The value of an operand: a literal below 32768, else one of the registers.
+    fn val(&self, word: u16) -> u16 {
+        match word {
+            0..32768 => word,
+            _ => self.registers[(word - 32768) as usize],
+        }
+    }
+
+    fn step(&mut self) -> Outcome {
+        let Some(&opcode) = self.mem.get(self.pc) else {
+            return Outcome::Fault;
+        };
+        let argc = match opcode {
+            0 | 18 | 21 => 0,
+            2 | 3 | 6 | 17 | 19 | 20 => 1,
+            1 | 7 | 8 | 14 | 15 | 16 => 2,
+            4 | 5 | 9..=13 => 3,
+            _ => return Outcome::Fault,
+        };
+        let mut args = [0; 3];
+        for (i, arg) in args.iter_mut().enumerate().take(argc) {
+            match self.mem.get(self.pc + 1 + i) {
+                Some(&word) if word <= 32775 => *arg = word,
+                _ => return Outcome::Fault,
+            }
+        }
+        // These ops store into the register named by their first operand.
+        let stores = matches!(opcode, 1 | 3 | 4 | 5 | 9..=15 | 20);
+        if stores && args[0] < 32768 {
+            return Outcome::Fault;
+        }
+        let dst = args[0].wrapping_sub(32768) as usize;
+        let [a, b, c] = args.map(|w| self.val(w));
+        let mut next = self.pc + 1 + argc;
+        match opcode {
+            0 => return Outcome::Halted,
+            1 => self.registers[dst] = b,
+            2 => self.stack.push(a),
+            3 => match self.stack.pop() {
+                Some(val) => self.registers[dst] = val,
+                None => return Outcome::Halted,
+            },
+            4 => self.registers[dst] = (b == c) as u16,
+            5 => self.registers[dst] = (b > c) as u16,
+            6 => next = a as usize,
+            7 if a != 0 => next = b as usize,
+            8 if a == 0 => next = b as usize,
+            7 | 8 => {}
+            9 => self.registers[dst] = ((b as u32 + c as u32) % 32768) as u16,
+            10 => self.registers[dst] = ((b as u32 * c as u32) % 32768) as u16,
+            11 => self.registers[dst] = if c == 0 { 0 } else { b % c },
+            12 => self.registers[dst] = b & c,
+            13 => self.registers[dst] = b | c,
+            14 => self.registers[dst] = b ^ 0x7fff,
+            15 => self.registers[dst] = self.mem[(b % 32768) as usize],
+            16 => self.mem[(a % 32768) as usize] = b,
+            17 => {
+                self.stack.push(next as u16);
+                next = a as usize;
+            }
+            18 => match self.stack.pop() {
+                Some(to) => next = to as usize,
+                None => return Outcome::Halted,
+            },
+            19 => self.output.push(a as u8),
+            20 => match self.input.pop_front() {
+                Some(byte) => self.registers[dst] = byte as u16,
+                None => return Outcome::InputExhausted,
+            },
+            _ => {}
+        }
+        self.pc = next;
+        Outcome::Ran
+    }
+
+    fn state(&self) -> State {
+        State {
+            pc: self.pc,
+            registers: self.registers,
+            stack: self.stack.clone(),
+            output: self.output.clone(),
+        }
+    }
+}
+
+fn machine(words: Vec<u16>, registers: &[u16; 8], input: &[u8]) -> Machine<Buffer> {
+    let mut machine = Machine::with_console(words, Buffer::default());
+    machine.set_mode(Mode::Lenient);
+    // Random input could start with the meta-command prefix.
+    machine.set_meta_prefix(None);
+    for (i, &val) in registers.iter().enumerate() {
+        machine.set_register(Reg::try_from(i as u8).unwrap(), val);
+    }
+    machine.console_mut().push_input(input);
+    machine
+}
+
+fn step(machine: &mut Machine<Buffer>) -> Outcome {
+    match machine.step() {
+        Ok(()) => Outcome::Ran,
+        Err(Error::Halted) => Outcome::Halted,
+        Err(Error::InputExhausted(_)) => Outcome::InputExhausted,
+        Err(_) => Outcome::Fault,
+    }
+}
+
+fn machine_state(machine: &Machine<Buffer>) -> State {
+    State {
+        pc: machine.pc() as usize,
+        registers: *machine.registers(),
+        stack: machine.stack().to_vec(),
+        output: machine.console().output.clone(),
+    }
+}
+
+/// Decodes and re-encodes every op in `words`.
+fn round_trip(words: &[u16]) -> Vec<u16> {
+    let mut out = Vec::with_capacity(words.len());
+    let mut addr = 0;
+    while addr < words.len() {
+        let op = Op::try_from(&words[addr..]).unwrap();
+        op.encode(&mut out);
+        addr += 1 + op.arg_count();
+    }
+    out
+}
+
+/// Steps both until they halt, fault or run out of steps, comparing them after every step.
+fn compare(machine: &mut Machine<Buffer>, reference: &mut Reference, words: &[u16]) {
+    for step_no in 0..MAX_STEPS {
+        let (got, want) = (step(machine), reference.step());
+        assert_eq!(
+            machine_state(machine),
+            reference.state(),
+            "diverged at step {step_no} of {words:?}"
+        );
+        assert_eq!(
+            got, want,
+            "different outcomes at step {step_no} of {words:?}"
+        );
+        if got != Outcome::Ran {
+            break;
+        }
+    }
+    assert!(
+        machine.mem() == reference.mem,
+        "memory differs after {words:?}"
+    );
+}
+
+#[test]
+fn machine_agrees_with_the_spec_on_random_programs() {
+    let mut rng = Rng(0x5eed_1234_abcd_ef01);
+    for _ in 0..CASES {
+        let count = 1 + rng.below(24) as usize;
+        let words = program(&mut rng, count);
+        let registers: [u16; 8] = std::array::from_fn(|_| rng.literal());
+        let input: Vec<u8> = (0..rng.below(8)).map(|_| rng.below(128) as u8).collect();
+        assert_eq!(round_trip(&words), words);
+
+        let mut machine = machine(words.clone(), &registers, &input);
+        let mut reference = Reference::new(words.clone(), &registers, &input);
+        compare(&mut machine, &mut reference, &words);
+    }
+}
+
+#[test]
+fn decode_encode_round_trip() {
+    let mut rng = Rng(0xdec0_de00_0000_0001);
+    for _ in 0..10_000 {
+        // Mostly valid words, with some invalid opcodes and operands thrown in.
+        let words: [u16; 4] = std::array::from_fn(|i| match rng.below(16) {
+            0 => rng.next() as u16,
+            _ if i == 0 => rng.below(22) as u16,
+            1..=5 => 32768 + rng.below(8) as u16,
+            _ => rng.literal(),
+        });
+        let Ok(op) = Op::try_from(&words[..]) else {
+            continue;
+        };
+        let mut encoded = Vec::new();
+        op.encode(&mut encoded);
+        assert_eq!(encoded.len(), 1 + op.arg_count());
+        assert_eq!(encoded, words[..encoded.len()], "{op:?}");
+        assert_eq!(Op::try_from(&encoded[..]).unwrap(), op);
+        for len in 0..encoded.len() {
+            assert!(
+                matches!(Op::try_from(&encoded[..len]), Err(Error::Truncated)),
+                "{op:?} cut to {len} words"
+            );
+        }
+    }
+}
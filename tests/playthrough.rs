@@ -0,0 +1,107 @@
+//! Plays `challenge.bin` through `script.txt`, plus the moves that show the codes it skips, and
+//! checks that every code recorded in `notes.md` shows up in the transcript, in order, and that
+//! the codes in it check out against `codes.txt`.
+
+use std::path::Path;
+
+use vmc::{
+    codes::{self, Hashes},
+    error::Error,
+    machine::{Buffer, Machine, load_rom},
+    op::Reg,
+    rom,
+    script::{Item, Line, Script},
+};
+
+/// Codes from the "Codes Found" list in `notes.md`, minus the ones struck through as invalid.
+fn noted_codes() -> Vec<(String, String)> {
+    let notes = std::fs::read_to_string("notes.md").unwrap();
+    notes
+        .lines()
+        .skip_while(|l| *l != "## Codes Found")
+        .skip(1)
+        .take_while(|l| !l.starts_with('#'))
+        .filter_map(|l| l.strip_prefix("- "))
+        .filter(|l| !l.starts_with("~~"))
+        .map(|l| {
+            let (code, note) = l.split_once("//").unwrap_or((l, ""));
+            (code.trim().to_owned(), note.trim().to_owned())
+        })
+        .collect()
+}
+
+/// `script.txt` with the moves that show the codes it skips: the tablet's, and the one at
+/// headquarters, where the teleporter goes before r7 is set.
+fn script() -> Script {
+    let mut script = Script::load(Path::new("script.txt")).unwrap();
+    let input = |text: &str| Line {
+        item: Item::Input(text.to_owned()),
+        path: None,
+        line: 0,
+    };
+    let set_r7 = script
+        .lines
+        .iter()
+        .position(|l| matches!(l.item, Item::SetReg(Reg::REG7, _)))
+        .expect("script.txt sets r7 before using the teleporter");
+    script.lines.insert(set_r7, input("use teleporter"));
+    script
+        .lines
+        .splice(0..0, [input("take tablet"), input("use tablet")]);
+    script
+}
+
+/// Runs the script, with the teleporter's check skipped for when the script sets r7.
+fn play() -> String {
+    let mem = load_rom(Path::new("challenge.bin")).unwrap();
+    let profile = rom::detect(&mem);
+    let mut machine = Machine::with_console(mem, Buffer::default());
+    machine.hack_teleporter(profile.teleporter_call.unwrap(), profile.r7.unwrap());
+    machine.set_script(script());
+    assert!(matches!(machine.run(), Err(Error::InputExhausted(_))));
+    String::from_utf8_lossy(&machine.console().output).into_owned()
+}
+
+#[test]
+fn script_finds_every_noted_code() {
+    let arch_spec = std::fs::read_to_string("arch-spec").unwrap();
+    let transcript = play();
+    let codes = noted_codes();
+    assert!(
+        codes.len() >= 8,
+        "expected the codes list in notes.md, got {codes:?}"
+    );
+
+    let mut rest = transcript.as_str();
+    for (code, note) in codes {
+        if note == "arch-spec" {
+            assert!(arch_spec.contains(&code), "{code} is not in arch-spec");
+            continue;
+        }
+        let found = [code.clone(), codes::mirror(&code)]
+            .into_iter()
+            .filter_map(|c| rest.find(&c).map(|i| i + c.len()))
+            .min();
+        let Some(end) = found else {
+            panic!("{code} ({note}) not found in order in the transcript");
+        };
+        rest = &rest[end..];
+    }
+    assert!(rest.contains("Congratulations; you have reached the end of the challenge!"));
+}
+
+#[test]
+fn every_code_shown_is_valid() {
+    let hashes: Hashes = std::fs::read_to_string("codes.txt")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let found = codes::find(&play());
+    assert_eq!(found.len(), 7, "{found:?}");
+    for code in &found {
+        assert!(hashes.check(code).is_some(), "{code:?} is not in codes.txt");
+    }
+    // The one in the mirror is only valid the right way round.
+    let mirrored = found.iter().find(|c| c.mirrored.is_some()).unwrap();
+    assert!(!hashes.contains(&mirrored.code));
+}